@@ -0,0 +1,99 @@
+use log::error;
+use opencv::prelude::*;
+use serde::Serialize;
+
+use crate::detector::{prepare_frame, BlobDetector, DetectorParams, MarkerDetector, Polarity};
+
+// frames sampled for tuning
+pub static TUNE_FRAMES: usize = 15;
+// gray levels between probed thresholds, the blob detector's own threshold step
+static THRESH_STEP: u32 = 10;
+// furthest a marker may wander between sampled frames to count as stable, in marker diameters
+static MAX_WANDER: f64 = 0.5;
+// band width in gray levels that scores full marks
+static GOOD_BAND_WIDTH: f64 = 100.0;
+
+// recommended detector thresholds
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct ThresholdTuning {
+    pub min_thresh: u32,
+    pub max_thresh: u32,
+    // 0 to 1, from how wide the band is and how clearly the marker shows in it
+    pub score: f64,
+}
+
+// mean confidence of the marker if every frame shows exactly one in the same place
+fn stable_marker(detector: &mut BlobDetector, frames: &Vec<Mat>) -> Option<f64> {
+    let mut markers = Vec::new();
+    for frame in frames.iter() {
+        let detections = detector.detect(frame);
+        if detections.len() != 1 {
+            return None;
+        }
+        markers.push(detections[0]);
+    }
+
+    let n = markers.len() as f64;
+    let mean_x = markers.iter().map(|marker| marker.x).sum::<f64>() / n;
+    let mean_y = markers.iter().map(|marker| marker.y).sum::<f64>() / n;
+    let mean_size = markers.iter().map(|marker| marker.size).sum::<f64>() / n;
+    let wander = markers.iter().map(|marker| (marker.x - mean_x).hypot(marker.y - mean_y)).fold(0.0, f64::max);
+    if wander > MAX_WANDER * mean_size {
+        return None;
+    }
+
+    return Some(markers.iter().map(|marker| marker.confidence).sum::<f64>() / n);
+}
+
+// sweeps the threshold range over sampled frames and picks the widest band of thresholds in which
+// exactly one stable marker is found, None if there is no such band
+pub fn tune_thresholds(frames: &Vec<Mat>, polarity: Polarity, params: DetectorParams) -> Option<ThresholdTuning> {
+    if frames.is_empty() {
+        return None;
+    }
+    let prepared = match frames.iter().map(|frame| prepare_frame(frame, polarity)).collect::<opencv::Result<Vec<Mat>>>() {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            error!("Could not prepare frames for threshold tuning ({:})", e);
+            return None;
+        }
+    };
+
+    // each probe covers two threshold steps, the blob detector needs a blob at two thresholds
+    let mut probes: Vec<(u32, Option<f64>)> = Vec::new();
+    let mut thresh = 0;
+    while thresh + THRESH_STEP < 255 {
+        let mut detector = BlobDetector::new(params, thresh, thresh + THRESH_STEP + 1);
+        probes.push((thresh, stable_marker(&mut detector, &prepared)));
+        thresh += THRESH_STEP;
+    }
+
+    // longest run of consecutive good probes
+    let mut best: Option<(usize, usize)> = None;
+    let mut run_start = None;
+    for (i, (_, confidence)) in probes.iter().enumerate() {
+        match (confidence, run_start) {
+            (Some(_), None) => run_start = Some(i),
+            (None, Some(start)) => {
+                if best.map(|(best_start, best_end)| i - start > best_end - best_start).unwrap_or(true) {
+                    best = Some((start, i));
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = run_start {
+        if best.map(|(best_start, best_end)| probes.len() - start > best_end - best_start).unwrap_or(true) {
+            best = Some((start, probes.len()));
+        }
+    }
+
+    let (start, end) = best?;
+    let min_thresh = probes[start].0;
+    let max_thresh = probes[end - 1].0 + THRESH_STEP + 1;
+    let confidence = probes[start..end].iter().filter_map(|(_, confidence)| *confidence).sum::<f64>() / (end - start) as f64;
+    let width_score = ((max_thresh - min_thresh) as f64 / GOOD_BAND_WIDTH).min(1.0);
+
+    return Some(ThresholdTuning { min_thresh, max_thresh, score: width_score * confidence });
+}
@@ -1,18 +1,34 @@
 extern crate ffmpeg_next as ffmpeg;
 
 use log::{error, info};
-use opencv::core::Ptr;
-use opencv::features2d::SimpleBlobDetector;
 use opencv::prelude::*;
 use serde::Serialize;
 use tauri::Window;
 use std::sync::mpsc::Receiver;
 use std::time::Instant;
 
-use crate::camera::camera_stream;
-use crate::shoot::{detect_circles, get_circle_detector, TracePoint};
+use crate::config::{update_camera_config, CameraConfig};
+use crate::detector::{Detector, DetectorKind, DetectorParams, Polarity};
+use crate::lens::{check_undistorter, Undistorter};
+use crate::mapping::{resolve_mapping, TargetMapping};
+use crate::orientation::Orientation;
+use crate::source::{camera_stream, FrameSource, FrameTime};
+use crate::tracker::Tracker;
+use crate::shoot::{TargetKind, TracePoint, RATIO1};
 
-// analyse trace to get calibration circle
+// smallest mean offset of the approach from the aim center in pixels to infer the camera
+// orientation from
+static MIN_APPROACH: f64 = 50.0;
+// seconds before the hold over which the approach onto the target is averaged
+static APPROACH_WINDOW: f64 = 1.0;
+// fewest detected points of the approach to infer the orientation from
+static MIN_APPROACH_POINTS: usize = 5;
+// approach points further than this many median deviations from the median offset are dropped
+static MAX_DEVIATION: f64 = 3.0;
+// seconds before the trigger over which the size of the aiming black is measured
+static SCALE_WINDOW: f64 = 1.0;
+
+// analyse trace to get calibration circle, its time is when the hold on it started
 fn calibrate(before_trace: &Vec<TracePoint>) -> Option<TracePoint> {
     let mut calibrate_point = None;
     let mut best_mean_dist = -1.0;
@@ -28,12 +44,14 @@ fn calibrate(before_trace: &Vec<TracePoint>) -> Option<TracePoint> {
                 // there has been 1s worth of data
 
                 // calculate average position and radius of detected circle in points
-                let mut avg_circle = TracePoint{ x: 0.0, y: 0.0, time: 0.0 };
+                let mut avg_circle = TracePoint{ x: 0.0, y: 0.0, time: 0.0, confidence: 0.0, interpolated: false, raw: None };
                 let n_points = points.len() as f64;
                 for point in points.iter() {
                     avg_circle.x += point.x / n_points;
                     avg_circle.y += point.y / n_points;
+                    avg_circle.confidence += point.confidence / n_points;
                 }
+                avg_circle.time = points.last().unwrap().time;
 
                 // calculate mean distance from points to average position
                 let mut mean_dist = 0.0;
@@ -59,10 +77,97 @@ fn calibrate(before_trace: &Vec<TracePoint>) -> Option<TracePoint> {
     return calibrate_point;
 }
 
+fn median(values: &mut Vec<f64>) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    return values[values.len() / 2];
+}
+
+// mean offset from the aim center of the aim coming down onto the target just before the hold,
+// with detection outliers dropped. None if too little of the approach was seen
+fn find_approach(before_trace: &Vec<TracePoint>, calibrate_point: &TracePoint) -> Option<(f64, f64)> {
+    let offsets: Vec<(f64, f64)> = before_trace
+        .iter()
+        .filter(|point| !point.interpolated)
+        .filter(|point| point.time >= calibrate_point.time - APPROACH_WINDOW && point.time < calibrate_point.time)
+        .map(|point| (point.x - calibrate_point.x, point.y - calibrate_point.y))
+        .collect();
+    if offsets.len() < MIN_APPROACH_POINTS {
+        return None;
+    }
+
+    let center = (
+        median(&mut offsets.iter().map(|offset| offset.0).collect()),
+        median(&mut offsets.iter().map(|offset| offset.1).collect()),
+    );
+    let distance = |offset: &(f64, f64)| (offset.0 - center.0).hypot(offset.1 - center.1);
+    let deviation = median(&mut offsets.iter().map(distance).collect());
+    let inliers: Vec<&(f64, f64)> = offsets
+        .iter()
+        .filter(|offset| distance(*offset) <= MAX_DEVIATION * deviation)
+        .collect();
+    if inliers.len() < MIN_APPROACH_POINTS {
+        return None;
+    }
+
+    let n = inliers.len() as f64;
+    return Some((
+        inliers.iter().map(|offset| offset.0).sum::<f64>() / n,
+        inliers.iter().map(|offset| offset.1).sum::<f64>() / n,
+    ));
+}
+
+// records an orientation inferred by a calibration in the camera config, a single calibration is
+// not trusted so the orientation only changes once two calibrations in a row agree on it.
+// Returns whether the orientation changed
+fn confirm_orientation(config: &mut CameraConfig, current: Orientation, inferred: Orientation) -> bool {
+    if inferred == current {
+        config.pending_orientation = None;
+        return false;
+    }
+
+    if config.pending_orientation == Some(inferred) {
+        config.orientation = inferred;
+        config.pending_orientation = None;
+        return true;
+    }
+
+    config.pending_orientation = Some(inferred);
+    return false;
+}
+
+// mm per pixel from the median apparent diameter of the aiming black while holding before the
+// trigger, None if it was not seen then
+fn measure_scale(marker_sizes: &Vec<(f64, f64)>, target: TargetKind) -> Option<f64> {
+    let last_time = marker_sizes.last()?.0;
+    let mut sizes: Vec<f64> = marker_sizes
+        .iter()
+        .filter(|(time, size)| *time >= last_time - SCALE_WINDOW && *size > 0.0)
+        .map(|(_, size)| *size)
+        .collect();
+    if sizes.is_empty() {
+        return None;
+    }
+
+    sizes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = sizes[sizes.len() / 2];
+    return Some(target.aiming_black_size() / median);
+}
+
 pub fn grab_calib_frames(
-    label: String,
+    source: Box<dyn FrameSource>,
+    camera_id: String,
     min_thresh: u32,
     max_thresh: u32,
+    polarity: Polarity,
+    detector_kind: DetectorKind,
+    detector_params: DetectorParams,
+    orientation: Orientation,
+    // keep the configured orientation instead of inferring it from the raise
+    orientation_locked: bool,
+    target: TargetKind,
+    undistorter: Option<Undistorter>,
+    // measured from four points, used instead of the calibrated one
+    fixed_mapping: Option<TargetMapping>,
     trigger_rx: Receiver<Instant>,
     window: Window,
     rx: Receiver<()>,
@@ -70,38 +175,61 @@ pub fn grab_calib_frames(
     // define and initialize frame state
     struct FrameState {
         frame_index: u32,
-        shot_start_time: Instant,
+        shot_start_time: Option<f64>,
         before_trace: Vec<TracePoint>,
+        // capture time and diameter in pixels of every detected aiming black
+        marker_sizes: Vec<(f64, f64)>,
         trigger_time: Option<Instant>,
-        detector: Ptr<SimpleBlobDetector>,
+        camera_id: String,
+        orientation: Orientation,
+        orientation_locked: bool,
+        target: TargetKind,
+        undistorter: Option<Undistorter>,
+        fixed_mapping: Option<TargetMapping>,
+        detector: Detector,
+        tracker: Tracker,
         trigger_rx: Receiver<Instant>
     }
 
     let frame_index = 0;
-    let detector = get_circle_detector(min_thresh, max_thresh);
-    let now = Instant::now();
+    let detector = Detector::new(detector_kind, detector_params, polarity, min_thresh, max_thresh);
     let frame_state = FrameState { 
         frame_index,
-        shot_start_time: now,
+        shot_start_time: None,
         before_trace: Vec::new(),
+        marker_sizes: Vec::new(),
         trigger_time: None,
+        camera_id,
+        orientation,
+        orientation_locked,
+        target,
+        undistorter,
+        fixed_mapping,
         detector,
+        tracker: Tracker::new(),
         trigger_rx
     };
 
-    let grab_frame = |frame: Mat, frame_state: &mut FrameState, window: &Window| -> bool {
+    let grab_frame = |frame: Mat, frame_time: FrameTime, frame_state: &mut FrameState, window: &Window| -> bool {
         #[derive(Serialize, Clone)]
         struct CalibFinishedPayload {
             success: bool,
             calibrate_point: [f64; 2],
+            orientation: Orientation,
+            // mm per pixel, None if it could not be measured
+            scale: Option<f64>,
+            // full frame pixels to target mm as shooting will map them
+            mapping: Option<TargetMapping>,
             error_msg: String
         }
 
-        let curr_time = Instant::now();
-        let time_since_shot_start = match frame_state.frame_index {
-            0 => 0.0,
-            _ => curr_time.duration_since(frame_state.shot_start_time).as_secs_f64()
-        };
+        // calibration is timed in capture time from the first frame
+        let curr_time = frame_time.pts;
+        if frame_state.shot_start_time.is_none() {
+            check_undistorter(&mut frame_state.undistorter, &frame);
+        }
+        let shot_start_time = *frame_state.shot_start_time.get_or_insert(curr_time);
+        let time_since_shot_start = curr_time - shot_start_time;
 
         match frame_state.trigger_rx.try_recv() {
             Ok(trigger_time) => {
@@ -111,17 +239,20 @@ pub fn grab_calib_frames(
             Err(_) => {}
         }
 
-        let keypoints = detect_circles(&frame, &mut frame_state.detector);
-        let detected_circle = keypoints.len() == 1;
+        let detections = frame_state.detector.detect(&frame);
+        let tracked = frame_state.tracker.update(&detections, curr_time);
 
-        if !detected_circle {
+        if tracked.is_none() {
             // circle not detected properly for 1min
-            if curr_time.duration_since(frame_state.shot_start_time).as_secs_f64() >= 60.0 {
+            if time_since_shot_start >= 60.0 {
                 info!("Calibration failed: undetected circle");
                 window
                     .emit("calibration_finished", CalibFinishedPayload{
                         success: false,
                         calibrate_point: [0.0, 0.0],
+                        orientation: frame_state.orientation,
+                        scale: None,
+                        mapping: None,
                         error_msg: "Target was not detected for 1min".to_string()
                     })
                     .unwrap();
@@ -130,16 +261,19 @@ pub fn grab_calib_frames(
 
             return true; // continue to next frame
         }
-        let circle = keypoints.get(0).unwrap();
-        info!("Detected circle (px): {:}, {:}", circle.pt.x, circle.pt.y);
+        let circle = tracked.unwrap();
+        info!("Detected circle (px): {:}, {:}", circle.x, circle.y);
 
-        if curr_time.duration_since(frame_state.shot_start_time).as_secs_f64() >= 120.0 {
+        if time_since_shot_start >= 120.0 {
             // timeout
             info!("Calibration failed: timeout");
             window
                 .emit("calibration_finished", CalibFinishedPayload{
                     success: false,
                     calibrate_point: [0.0, 0.0],
+                    orientation: frame_state.orientation,
+                    scale: None,
+                    mapping: None,
                     error_msg: "Calibrating for more than 2min - timeout".to_string()
                 })
                 .unwrap();
@@ -147,20 +281,63 @@ pub fn grab_calib_frames(
         }
 
         frame_state.before_trace.push(TracePoint{
-            x: circle.pt.x as f64,
-            y: circle.pt.y as f64,
+            x: circle.x,
+            y: circle.y,
             time: time_since_shot_start,
+            confidence: circle.confidence,
+            interpolated: circle.interpolated,
+            raw: None,
         });
+        if !circle.interpolated {
+            frame_state.marker_sizes.push((time_since_shot_start, circle.size));
+        }
 
         if frame_state.trigger_time.is_some() {
             // received trigger
             let calibrate_point = calibrate(&frame_state.before_trace);
             if calibrate_point.is_some() {
                 info!("Calibration success!");
+                if !frame_state.orientation_locked {
+                    let approach = find_approach(&frame_state.before_trace, &calibrate_point.unwrap());
+                    match approach.and_then(|approach| frame_state.orientation.infer(approach, MIN_APPROACH)) {
+                        Some(orientation) => {
+                            let mut confirmed = false;
+                            let current = frame_state.orientation;
+                            update_camera_config(window, &frame_state.camera_id, |config| {
+                                confirmed = confirm_orientation(config, current, orientation);
+                            });
+
+                            if confirmed {
+                                info!("Inferred camera orientation {:?}", orientation);
+                                frame_state.orientation = orientation;
+                            } else if orientation != current {
+                                info!("Inferred camera orientation {:?}, kept {:?} until another calibration agrees", orientation, current);
+                            }
+                        }
+                        None => info!("Approach onto the target was not clear enough to infer camera orientation"),
+                    }
+                }
+
+                let scale = measure_scale(&frame_state.marker_sizes, frame_state.target);
+                match scale {
+                    Some(scale) => info!("Measured scale {:}mm/px", scale),
+                    None => info!("Could not measure scale"),
+                }
+                let mapping = resolve_mapping(
+                    frame_state.fixed_mapping,
+                    [calibrate_point.unwrap().x, calibrate_point.unwrap().y],
+                    scale.unwrap_or(RATIO1),
+                    frame_state.orientation,
+                    &frame_state.undistorter,
+                );
+
                 window
                     .emit("calibration_finished", CalibFinishedPayload{
                         success: true,
                         calibrate_point: [calibrate_point.unwrap().x, calibrate_point.unwrap().y],
+                        orientation: frame_state.orientation,
+                        scale,
+                        mapping: Some(mapping),
                         error_msg: "".to_string()
                     })
                     .unwrap();
@@ -170,6 +347,9 @@ pub fn grab_calib_frames(
                     .emit("calibration_finished", CalibFinishedPayload{
                         success: false,
                         calibrate_point: [0.0, 0.0],
+                        orientation: frame_state.orientation,
+                        scale: None,
+                        mapping: None,
                         error_msg: "Shot too quickly".to_string()
                     })
                     .unwrap();
@@ -183,7 +363,7 @@ pub fn grab_calib_frames(
         return true; // continue onto next frame
     };
 
-    match camera_stream(label, rx, frame_state, grab_frame, window) {
+    match camera_stream(source, rx, frame_state, grab_frame, window) {
         Ok(()) => (),
         Err(e) => {
             error!("Could not read frames from camera ({:})", e.to_string());
@@ -192,3 +372,104 @@ pub fn grab_calib_frames(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64, time: f64) -> TracePoint {
+        TracePoint { x, y, time, confidence: 1.0, interpolated: false, raw: None }
+    }
+
+    // aim coming down onto the hold at (640, 360) from `from` pixels away along `direction` over
+    // the second before the hold, at 60fps, then holding for a second
+    fn approach_trace(direction: (f64, f64), from: f64) -> (Vec<TracePoint>, TracePoint) {
+        let hold = point(640.0, 360.0, 2.0);
+        let mut trace = Vec::new();
+        for i in 0..60 {
+            let progress = i as f64 / 60.0;
+            let distance = from * (1.0 - progress);
+            trace.push(point(hold.x + direction.0 * distance, hold.y + direction.1 * distance, 1.0 + progress));
+        }
+        for i in 0..60 {
+            trace.push(point(hold.x, hold.y, 2.0 + i as f64 / 60.0));
+        }
+        return (trace, hold);
+    }
+
+    #[test]
+    fn approach_from_each_side_infers_the_rotation() {
+        // coming down from the top of the target, in the image of each rotation of the camera
+        let directions = [(0, (1.0, 0.0)), (90, (0.0, 1.0)), (180, (-1.0, 0.0)), (270, (0.0, -1.0))];
+        for (rotation, direction) in directions {
+            let (mut trace, hold) = approach_trace(direction, 200.0);
+            // a false detection on the other side of the target is dropped
+            trace[30] = point(hold.x - 300.0 * direction.0, hold.y - 300.0 * direction.1, trace[30].time);
+
+            let approach = find_approach(&trace, &hold).unwrap();
+            let inferred = Orientation::default().infer(approach, MIN_APPROACH);
+            assert_eq!(inferred, Some(Orientation { rotation, ..Orientation::default() }), "approach {:?}", approach);
+        }
+    }
+
+    #[test]
+    fn mirrored_approach_keeps_the_mirroring() {
+        let mirrored = Orientation { mirror_horizontal: true, ..Orientation::default() };
+        let (trace, hold) = approach_trace((-1.0, 0.0), 200.0);
+        let approach = find_approach(&trace, &hold).unwrap();
+        assert_eq!(mirrored.infer(approach, MIN_APPROACH), Some(mirrored));
+    }
+
+    #[test]
+    fn unclear_approach_infers_nothing() {
+        // the aim barely moved onto the target
+        let (trace, hold) = approach_trace((1.0, 0.0), 60.0);
+        let approach = find_approach(&trace, &hold).unwrap();
+        assert_eq!(Orientation::default().infer(approach, MIN_APPROACH), None);
+
+        // the approach was not seen, the aim was only found holding
+        let (trace, hold) = approach_trace((1.0, 0.0), 200.0);
+        let mut trace: Vec<TracePoint> = trace.into_iter().filter(|point| point.time >= 1.95).collect();
+        assert_eq!(find_approach(&trace, &hold), None);
+
+        // predicted points are not taken for an approach
+        let (full_trace, _) = approach_trace((1.0, 0.0), 200.0);
+        trace = full_trace.into_iter().map(|point| TracePoint { interpolated: point.time < 2.0, ..point }).collect();
+        assert_eq!(find_approach(&trace, &hold), None);
+    }
+
+    #[test]
+    fn orientation_changes_once_two_calibrations_agree() {
+        let current = Orientation::default();
+        let turned = Orientation { rotation: 90, ..Orientation::default() };
+        let mut config = CameraConfig::default();
+
+        // the first calibration only proposes the new orientation
+        assert!(!confirm_orientation(&mut config, current, turned));
+        assert_eq!(config.orientation, current);
+        assert_eq!(config.pending_orientation, Some(turned));
+
+        // the second agreeing one applies it
+        assert!(confirm_orientation(&mut config, current, turned));
+        assert_eq!(config.orientation, turned);
+        assert_eq!(config.pending_orientation, None);
+    }
+
+    #[test]
+    fn disagreeing_calibrations_keep_the_orientation() {
+        let current = Orientation::default();
+        let turned = Orientation { rotation: 90, ..Orientation::default() };
+        let flipped = Orientation { rotation: 180, ..Orientation::default() };
+        let mut config = CameraConfig::default();
+
+        assert!(!confirm_orientation(&mut config, current, turned));
+        // a different proposal replaces the pending one
+        assert!(!confirm_orientation(&mut config, current, flipped));
+        assert_eq!(config.pending_orientation, Some(flipped));
+        // a calibration agreeing with the current orientation drops the proposal
+        assert!(!confirm_orientation(&mut config, current, current));
+        assert_eq!(config.pending_orientation, None);
+        assert!(!confirm_orientation(&mut config, current, flipped));
+        assert_eq!(config.orientation, current);
+    }
+}
@@ -4,52 +4,100 @@ extern crate base64;
 use ffmpeg::ffi::*;
 use ffmpeg::format::{context::Input, Pixel};
 use ffmpeg::software::scaling::{context::Context, flag::Flags};
+use ffmpeg::util::error::EAGAIN;
 use ffmpeg::util::frame::video::Video;
-use ffmpeg::{Dictionary, Error};
+use ffmpeg::{Dictionary, Error, Packet};
+use opencv::core::Scalar;
 use opencv::prelude::*;
+use serde::{Deserialize, Serialize};
 use tauri::Window;
 
+use std::collections::BTreeMap;
 use std::ffi::CString;
 use std::path::Path;
 use std::ptr;
-use std::sync::mpsc::{Receiver, TryRecvError};
+use std::os::raw::{c_int, c_void};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 use log::{info, error, warn};
 
+use crate::controls::{apply_controls, CameraControls};
+use crate::playback::{Playback, PlaybackCommand, PlaybackEvent};
+use crate::recorder::{Recorder, Recording};
+use crate::source::{terminate_requested, Frame, FrameFormat, FrameSource, FrameTime};
+
 fn path_to_cstr<P: AsRef<Path>>(path: &P) -> CString {
     CString::new(path.as_ref().as_os_str().to_str().unwrap()).unwrap()
 }
 
-fn get_camera_input(label: String) -> Result<Input, Error> {
-    let mut options = Dictionary::new();
-    let is_path = label.contains("/") || label.contains("\\"); 
-    if label.contains("USB") {
-        options.set("framerate", "120");
-        options.set("vcodec", "mjpeg");
-        options.set("video_size", "1280x720");
-    } else if is_path {
-        // pass 
-    } else {
-        options.set("framerate", "30");
-        options.set("pixel_format", "bgr0");
+pub fn is_v4l2_device(label: &str) -> bool {
+    cfg!(target_os = "linux") && label.starts_with("/dev/")
+}
+
+// how long opening a device may block before it is given up on
+static OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+// how long a device may go without delivering a frame before it is considered lost
+static STALL_TIMEOUT: Duration = Duration::from_millis(2000);
+static MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+// devices are read without blocking, this is how long to wait before asking again
+static NO_PACKET_WAIT: Duration = Duration::from_millis(2);
+// consecutive read errors after which a file is given up on
+static MAX_READ_ERRORS: u32 = 10;
+
+// interrupts blocking ffmpeg calls once a device has not made progress for its timeout
+pub struct StallWatch {
+    deadline: Mutex<(Instant, Duration)>,
+}
+
+impl StallWatch {
+    pub fn new() -> StallWatch {
+        StallWatch { deadline: Mutex::new((Instant::now() + OPEN_TIMEOUT, OPEN_TIMEOUT)) }
+    }
+
+    // start a new phase with its own timeout
+    pub fn arm(&self, timeout: Duration) {
+        *self.deadline.lock().unwrap() = (Instant::now() + timeout, timeout);
+    }
+
+    // progress was made, push the deadline back
+    pub fn feed(&self) {
+        let mut deadline = self.deadline.lock().unwrap();
+        deadline.0 = Instant::now() + deadline.1;
     }
 
+    pub fn is_stalled(&self) -> bool {
+        Instant::now() > self.deadline.lock().unwrap().0
+    }
+
+    // how long the deadline has passed, zero while progress is being made
+    pub fn stalled_for(&self) -> Duration {
+        Instant::now().saturating_duration_since(self.deadline.lock().unwrap().0)
+    }
+}
+
+unsafe extern "C" fn stall_interrupt(opaque: *mut c_void) -> c_int {
+    let watch = &*(opaque as *const StallWatch);
+    watch.is_stalled() as c_int
+}
+
+fn open_input(label: &str, format: &CString, options: Dictionary, watch: Option<&StallWatch>) -> Result<Input, Error> {
     unsafe {
-        let mut ps = ptr::null_mut();
-        let mut opts = options.disown();
-        let format;
-        let mut new_label = label.clone();
-        if is_path {
-            format = CString::new("").unwrap();
-        } else if cfg!(target_os = "macos") {
-            format = CString::new("avfoundation").unwrap();
-        } else if cfg!(windows) {
-            format = CString::new("dshow").unwrap();
-            new_label = format!("video={:}", label);
-        } else{
-            error!("Operating system not supported");
-            return Err(Error::Bug);
+        let mut ps = avformat_alloc_context();
+        if let Some(watch) = watch {
+            // watch must outlive the input, which FfmpegSource guarantees by declaring its connection
+            // before the shared watch so the input is dropped first
+            (*ps).interrupt_callback = AVIOInterruptCB {
+                callback: Some(stall_interrupt),
+                opaque: watch as *const StallWatch as *mut c_void,
+            };
+            // device demuxers block waiting for a frame without polling the interrupt callback,
+            // reading without blocking lets read_frame check the watch itself
+            (*ps).flags |= AVFMT_FLAG_NONBLOCK as c_int;
         }
-        let path = path_to_cstr(&new_label);
+        let mut opts = options.disown();
+        let path = path_to_cstr(&label);
         let fmt = av_find_input_format(format.as_ptr());
 
         if fmt == ptr::null_mut() {
@@ -73,84 +121,585 @@ fn get_camera_input(label: String) -> Result<Input, Error> {
     }
 }
 
-pub fn camera_stream<T>(label: String, rx: Receiver<()>, mut state: T, grab_frame: fn(Mat, &mut T, &Window) -> bool, window: Window) -> Result<(), Error> {
-    info!("Starting camera {:}", label);
-    
-    let mut input = match get_camera_input(label) {
-        Ok(input) => input,
-        Err(e) => {
-            error!("Could not initialize camera ({:})", e.to_string());
-            // TODO: emit toast error event with error "Could not initialize camera".to_string()
-            return Err(Error::Bug);
+pub fn is_camera_path(label: &str) -> bool {
+    !is_v4l2_device(label) && (label.contains("/") || label.contains("\\"))
+}
+
+// name of the ffmpeg input device used to capture cameras on this OS
+pub fn camera_format_name() -> Option<&'static str> {
+    if cfg!(target_os = "macos") {
+        Some("avfoundation")
+    } else if cfg!(windows) {
+        Some("dshow")
+    } else if cfg!(target_os = "linux") {
+        Some("video4linux2")
+    } else {
+        None
+    }
+}
+
+pub fn open_camera(label: &str, options: Dictionary, watch: Option<&StallWatch>) -> Result<Input, Error> {
+    let format;
+    let mut new_label = label.to_string();
+    if is_camera_path(label) {
+        format = CString::new("").unwrap();
+    } else {
+        format = match camera_format_name() {
+            Some(name) => CString::new(name).unwrap(),
+            None => {
+                error!("Operating system not supported");
+                return Err(Error::Bug);
+            }
+        };
+        if cfg!(windows) {
+            new_label = format!("video={:}", label);
         }
-    };
+    }
+
+    open_input(&new_label, &format, options, watch)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CaptureProfile {
+    pub framerate: Option<f64>,
+    pub video_size: Option<String>,
+    pub vcodec: Option<String>,
+    pub pixel_format: Option<String>,
+    pub extra_options: BTreeMap<String, String>,
+    // applied after the device is opened, they are not ffmpeg options
+    pub controls: CameraControls,
+}
+
+impl CaptureProfile {
+    // profile used for cameras that have none saved, `name` is the friendly name of the device
+    // opened by `label`, the device ids of built-in cameras can mention USB too
+    pub fn default_for(label: &str, name: &str) -> CaptureProfile {
+        let mut profile = CaptureProfile::default();
+        if is_v4l2_device(label) || name.to_uppercase().contains("USB") {
+            // v4l2 has no USB hint in its device paths so always ask for the USB camera mode
+            profile.framerate = Some(120.0);
+            profile.vcodec = Some("mjpeg".to_string());
+            profile.video_size = Some("1280x720".to_string());
+        } else if is_camera_path(label) {
+            // pass 
+        } else {
+            profile.framerate = Some(30.0);
+            profile.pixel_format = Some("bgr0".to_string());
+        }
+
+        return profile;
+    }
+
+    fn to_dictionary(&self, label: &str) -> Dictionary {
+        let mut options = Dictionary::new();
+        if let Some(framerate) = self.framerate {
+            options.set("framerate", &framerate.to_string());
+        }
+        if let Some(video_size) = &self.video_size {
+            options.set("video_size", video_size);
+        }
+        if let Some(vcodec) = &self.vcodec {
+            // v4l2 calls the compressed capture format its input format
+            let key = if is_v4l2_device(label) { "input_format" } else { "vcodec" };
+            options.set(key, vcodec);
+        }
+        if let Some(pixel_format) = &self.pixel_format {
+            options.set("pixel_format", pixel_format);
+        }
+        for (key, value) in self.extra_options.iter() {
+            options.set(key, value);
+        }
+
+        return options;
+    }
+}
+
+fn get_camera_input(label: &str, profile: &CaptureProfile, watch: Option<&StallWatch>) -> Result<Input, Error> {
+    // options to try in order, the last set is always the device's default mode
+    let mut candidates = Vec::new();
+    if *profile != CaptureProfile::default() {
+        candidates.push(profile.to_dictionary(label));
+    }
+    candidates.push(Dictionary::new());
+
+    let n_candidates = candidates.len();
+    for (i, options) in candidates.into_iter().enumerate() {
+        if let Some(watch) = watch {
+            watch.arm(OPEN_TIMEOUT);
+        }
+        match open_camera(label, options, watch) {
+            Ok(input) => {
+                apply_controls(label, &profile.controls);
+                return Ok(input);
+            }
+            Err(e) if i + 1 < n_candidates => {
+                warn!("Could not open {:} with profile {:?} ({:}), falling back to default mode", label, profile, e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(Error::Bug)
+}
+
+// maps presentation timestamps onto a continuous capture timeline and the wall clock
+struct CaptureClock {
+    anchor: Option<(Instant, f64)>,
+    last_pts: Option<f64>,
+    last_arrival: Instant,
+    offset: f64,
+    rebase: bool,
+}
+
+impl CaptureClock {
+    fn new() -> CaptureClock {
+        CaptureClock { anchor: None, last_pts: None, last_arrival: Instant::now(), offset: 0.0, rebase: false }
+    }
+
+    // timestamps of the next frame are unrelated to the previous ones
+    fn discontinuity(&mut self) {
+        self.rebase = true;
+        self.anchor = None;
+    }
+
+    fn frame_time(&mut self, raw_pts: Option<f64>) -> FrameTime {
+        let now = Instant::now();
+        let pts = match raw_pts {
+            Some(raw_pts) => {
+                if self.rebase {
+                    // continue from the last frame, leaving a gap as long as we waited
+                    if let Some(last_pts) = self.last_pts {
+                        self.offset = last_pts + now.duration_since(self.last_arrival).as_secs_f64() - raw_pts;
+                    }
+                    self.rebase = false;
+                }
+                raw_pts + self.offset
+            }
+            // frames without timestamps are assumed to be captured on arrival
+            None => match self.anchor {
+                Some((anchor_time, anchor_pts)) => anchor_pts + now.duration_since(anchor_time).as_secs_f64(),
+                None => self.last_pts.unwrap_or(0.0),
+            },
+        };
+
+        let instant = match (self.anchor, self.last_pts) {
+            // timestamps jumped backwards or far ahead (stall), start over
+            (Some(_), Some(last_pts)) if pts < last_pts || pts - last_pts > 1.0 => None,
+            (Some((anchor_time, anchor_pts)), _) if pts >= anchor_pts => Some(anchor_time + Duration::from_secs_f64(pts - anchor_pts)),
+            _ => None,
+        };
+
+        // a frame cannot be captured after it arrived, so anchor on the lowest latency seen
+        let instant = match instant {
+            Some(instant) if instant <= now => instant,
+            _ => {
+                self.anchor = Some((now, pts));
+                now
+            }
+        };
+        self.last_pts = Some(pts);
+        self.last_arrival = now;
+
+        FrameTime { pts, instant }
+    }
+}
+
+// an opened input and everything needed to decode its video stream
+struct CameraConnection {
+    input: Input,
+    stream_index: usize,
+    time_base: f64,
+    decoder: ffmpeg::decoder::Video,
+}
+
+fn connect(label: &str, profile: &CaptureProfile, watch: Option<&StallWatch>) -> Result<CameraConnection, Error> {
+    let input = get_camera_input(label, profile, watch)?;
 
     let stream = match input.streams().best(ffmpeg::media::Type::Video) {
         Some(s) => s,
         None => {
             error!("Could not get video stream from camera");
-            return Err(Error::Bug);
+            return Err(Error::StreamNotFound);
         }
     };
 
     let stream_index = stream.index();
+    let time_base = f64::from(stream.time_base());
 
     let context_decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
-    let mut decoder = context_decoder.decoder().video()?;
+    let decoder = context_decoder.decoder().video()?;
+
+    Ok(CameraConnection { input, stream_index, time_base, decoder })
+}
+
+// keep trying to open a lost device, backing off between attempts, until it is back or the
+// stream is terminated
+fn reconnect(label: &str, profile: &CaptureProfile, watch: &StallWatch, rx: &Receiver<()>) -> Option<CameraConnection> {
+    let mut backoff = Duration::from_millis(250);
+    loop {
+        // waiting to reconnect is progress, the capture thread is not stuck
+        watch.arm(OPEN_TIMEOUT);
+        match rx.recv_timeout(backoff) {
+            Ok(_) | Err(RecvTimeoutError::Disconnected) => {
+                info!("Terminating camera stream thread while reconnecting");
+                return None;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+
+        info!("Reconnecting to camera {:}", label);
+        match connect(label, profile, Some(watch)) {
+            Ok(connection) => return Some(connection),
+            Err(e) => warn!("Could not reconnect to camera ({:})", e.to_string()),
+        }
+        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+    }
+}
 
-    let mut scaler = Context::get(
-        decoder.format(),
-        decoder.width(),
-        decoder.height(),
-        Pixel::RGB24,
-        decoder.width(),
-        decoder.height(),
-        Flags::BICUBIC,
-    )?;
+// swscale contexts are only ever used by the thread owning the source
+struct Scaler(Context);
+unsafe impl Send for Scaler {}
 
-    let state_ref = &mut state;
+// copies the first plane of a scaled frame into a Mat row by row, ffmpeg pads rows to its
+// alignment so the plane is not contiguous for widths that are not a multiple of it
+fn frame_to_mat(frame: &Video, format: FrameFormat) -> Result<Mat, opencv::Error> {
+    let row_size = frame.width() as usize * format.channels() as usize;
+    let stride = frame.stride(0);
+    let data = frame.data(0);
 
-    for (stream, packet) in input.packets() {
-        if stream.index() == stream_index {
-            decoder.send_packet(&packet)?;
+    let mut mat = Mat::new_rows_cols_with_default(frame.height() as i32, frame.width() as i32, format.mat_type(), Scalar::all(0.0))?;
+    for (row, out) in mat.data_bytes_mut()?.chunks_exact_mut(row_size).enumerate() {
+        out.copy_from_slice(&data[row * stride..row * stride + row_size]);
+    }
 
-            let mut decoded = Video::empty();
-            if decoder.receive_frame(&mut decoded).is_ok() {
-                let mut rgb_frame = Video::empty();
-                scaler.run(&decoded, &mut rgb_frame)?;
-
-                let mat = match Mat::from_slice(rgb_frame.data(0)) {
-                    Ok(res) => {
-                        match res.reshape(3, decoder.height() as i32) {
-                            Ok(res2) => res2,
-                            Err(error) =>{
-                                error!("Could not reshape matrix ({:})", error);
-                                continue;
-                            } 
+    return Ok(mat);
+}
+
+// why reading a frame from a connection stopped
+enum ReadResult {
+    Frame(Frame),
+    Terminated,
+    Finished,
+    Lost,
+    // a file could not be read any further
+    Failed(Error),
+}
+
+// camera device or video file read through ffmpeg
+pub struct FfmpegSource {
+    label: String,
+    profile: CaptureProfile,
+    format: FrameFormat,
+    window: Window,
+    // dropped before `watch` so that no input outlives its interrupt callback
+    connection: Option<CameraConnection>,
+    scaler: Option<Scaler>,
+    playback: Option<Playback>,
+    recording: Option<Recording>,
+    recorder: Option<Recorder>,
+    clock: CaptureClock,
+    packet: Packet,
+    // shared so its address stays valid for the interrupt callbacks while the source moves and
+    // so processing can tell when the capture thread is stuck
+    watch: Arc<StallWatch>,
+    is_device: bool,
+    // the device has to be reconnected before reading
+    lost: bool,
+}
+
+impl FfmpegSource {
+    fn new(label: String, profile: CaptureProfile, format: FrameFormat, playback: Option<Playback>, window: Window, is_device: bool) -> FfmpegSource {
+        FfmpegSource {
+            label,
+            profile,
+            format,
+            window,
+            connection: None,
+            scaler: None,
+            playback,
+            recording: None,
+            recorder: None,
+            clock: CaptureClock::new(),
+            packet: Packet::empty(),
+            watch: Arc::new(StallWatch::new()),
+            is_device,
+            lost: false,
+        }
+    }
+
+    pub fn device(label: String, profile: CaptureProfile, format: FrameFormat, window: Window) -> FfmpegSource {
+        FfmpegSource::new(label, profile, format, None, window, true)
+    }
+
+    // recorded files are paced by their timestamps, devices deliver frames in real time
+    pub fn file(path: String, profile: CaptureProfile, format: FrameFormat, playback_rx: Receiver<PlaybackCommand>, window: Window) -> FfmpegSource {
+        FfmpegSource::new(path, profile, format, Some(Playback::new(playback_rx)), window, false)
+    }
+
+    fn watch(&self) -> Option<&StallWatch> {
+        if self.is_device { Some(&*self.watch) } else { None }
+    }
+
+    fn set_connection(&mut self, connection: CameraConnection) -> Result<(), Error> {
+        let decoder = &connection.decoder;
+        // swscale takes the luma plane as is for gray output, skipping the colour conversion
+        let pixel = match self.format {
+            FrameFormat::Gray => Pixel::GRAY8,
+            FrameFormat::Rgb => Pixel::RGB24,
+        };
+        let scaler = Context::get(
+            decoder.format(),
+            decoder.width(),
+            decoder.height(),
+            pixel,
+            decoder.width(),
+            decoder.height(),
+            Flags::BICUBIC,
+        )?;
+        self.scaler = Some(Scaler(scaler));
+
+        if let Some(recording) = self.recording.take() {
+            let stream = connection.input.stream(connection.stream_index).unwrap();
+            match Recorder::new(recording, &stream) {
+                Ok(recorder) => self.recorder = Some(recorder),
+                Err(e) => error!("Could not start recording ({:})", e.to_string()),
+            }
+        }
+
+        self.connection = Some(connection);
+        self.watch.arm(STALL_TIMEOUT);
+        Ok(())
+    }
+
+    // reconnects a lost device, false if the stream was terminated first
+    fn recover(&mut self, rx: &Receiver<()>) -> Result<bool, Error> {
+        // state of the consumer is untouched, it just sees a gap in capture time
+        error!("Lost camera {:}", self.label);
+        self.window.emit("camera_lost", self.label.clone()).unwrap();
+
+        // close the lost device before opening it again
+        self.connection = None;
+        let connection = match reconnect(&self.label, &self.profile, &self.watch, rx) {
+            Some(connection) => connection,
+            None => return Ok(false),
+        };
+        self.set_connection(connection)?;
+        self.lost = false;
+        self.clock.discontinuity();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.discontinuity();
+        }
+        info!("Restored camera {:}", self.label);
+        self.window.emit("camera_restored", self.label.clone()).unwrap();
+
+        Ok(true)
+    }
+
+    fn read_frame(&mut self, rx: &Receiver<()>) -> ReadResult {
+        let watch = if self.is_device { Some(&*self.watch) } else { None };
+        let stalled = || watch.map(|watch| watch.is_stalled()).unwrap_or(false);
+        let connection = self.connection.as_mut().unwrap();
+        let scaler = &mut self.scaler.as_mut().unwrap().0;
+        let mut read_errors = 0;
+
+        loop {
+            match self.playback.as_mut() {
+                Some(playback) => match playback.poll(rx) {
+                    PlaybackEvent::Continue => {}
+                    PlaybackEvent::Seek(seconds) => {
+                        let ts = (seconds * AV_TIME_BASE as f64) as i64;
+                        match connection.input.seek(ts, ..ts) {
+                            Ok(()) => {
+                                connection.decoder.flush();
+                                self.clock.discontinuity();
+                            }
+                            Err(error) => error!("Could not seek to {:}s ({:})", seconds, error),
                         }
-                    },
-                    Err(error) => {
-                        error!("Could not convert frame to Mat ({:})", error);
                         continue;
                     }
-                };
+                    PlaybackEvent::Terminate => {
+                        info!("Terminating camera stream thread");
+                        return ReadResult::Terminated;
+                    }
+                },
+                None => {
+                    if terminate_requested(rx) {
+                        info!("Terminating camera stream thread");
+                        return ReadResult::Terminated;
+                    }
+                }
+            }
 
-                if !grab_frame(mat, state_ref, &window) {
-                    break;
+            match self.packet.read(&mut connection.input) {
+                Ok(()) => {}
+                Err(Error::Eof) => match self.playback.as_mut() {
+                    Some(playback) => {
+                        // keep the file open at its end so it can be seeked back into
+                        if !playback.is_paused() {
+                            info!("Playback reached end of file");
+                            playback.pause();
+                            self.window.emit("playback_ended", {}).unwrap();
+                        }
+                        continue;
+                    }
+                    None if watch.is_some() => {
+                        warn!("Camera stream ended unexpectedly");
+                        return ReadResult::Lost;
+                    }
+                    None => return ReadResult::Finished,
+                },
+                Err(Error::Other { errno }) if errno == EAGAIN => {
+                    // no packet yet from a device read without blocking
+                    if stalled() {
+                        warn!("Camera stalled");
+                        return ReadResult::Lost;
+                    }
+                    sleep(NO_PACKET_WAIT);
+                    continue;
                 }
+                Err(e) => {
+                    if stalled() {
+                        warn!("Camera stalled ({:})", e.to_string());
+                        return ReadResult::Lost;
+                    }
+                    if watch.is_some() && e != Error::Exit {
+                        warn!("Could not read from camera ({:})", e.to_string());
+                        return ReadResult::Lost;
+                    }
+
+                    // retrying is for passing errors, a broken file would never end the stream
+                    read_errors += 1;
+                    if read_errors < MAX_READ_ERRORS {
+                        continue;
+                    }
+                    error!("Could not read from {:} ({:})", self.label, e.to_string());
+                    return if watch.is_some() { ReadResult::Lost } else { ReadResult::Failed(e) };
+                }
+            }
+            read_errors = 0;
+
+            if self.packet.stream() != connection.stream_index {
+                if stalled() {
+                    warn!("Camera stalled");
+                    return ReadResult::Lost;
+                }
+                continue;
+            }
+
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.write(&self.packet);
+            }
+
+            // a single corrupt packet is skipped, a device sending nothing else is caught by the
+            // stall watch as it is only fed decoded frames
+            if let Err(error) = connection.decoder.send_packet(&self.packet) {
+                warn!("Could not decode packet ({:})", error);
+                if stalled() {
+                    warn!("Camera stalled, no packet could be decoded");
+                    return ReadResult::Lost;
+                }
+                continue;
+            }
+
+            let mut decoded = Video::empty();
+            if connection.decoder.receive_frame(&mut decoded).is_err() {
+                if stalled() {
+                    warn!("Camera stalled, no frame could be decoded");
+                    return ReadResult::Lost;
+                }
+                continue;
+            }
+            if let Some(watch) = watch {
+                watch.feed();
+            }
+
+            let pts = decoded.timestamp().map(|pts| pts as f64 * connection.time_base);
+            if let (Some(playback), Some(pts)) = (self.playback.as_mut(), pts) {
+                playback.wait_for(pts);
+            }
+            let time = self.clock.frame_time(pts);
+
+            let mut scaled = Video::empty();
+            if let Err(error) = scaler.run(&decoded, &mut scaled) {
+                error!("Could not convert frame to {:?} ({:})", self.format, error);
+                continue;
             }
+
+            let mat = match frame_to_mat(&scaled, self.format) {
+                Ok(mat) => mat,
+                Err(error) => {
+                    error!("Could not convert frame to Mat ({:})", error);
+                    continue;
+                }
+            };
+
+            return ReadResult::Frame(Frame { mat, time });
         }
+    }
+}
 
-        match rx.try_recv() {
-            Ok(_) | Err(TryRecvError::Disconnected) => {
-                info!("Terminating camera stream thread");
-                break;
+impl FrameSource for FfmpegSource {
+    fn next_frame(&mut self, rx: &Receiver<()>) -> Result<Option<Frame>, Error> {
+        if self.connection.is_none() && !self.lost {
+            info!("Starting camera {:}", self.label);
+            let result = match connect(&self.label, &self.profile, self.watch()) {
+                Ok(connection) => self.set_connection(connection),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!("Could not initialize camera ({:})", e.to_string());
+                self.window
+                    .emit("show_message", "Could not initialize camera".to_string())
+                    .unwrap();
+                return Err(e);
+            }
+        }
+
+        loop {
+            if self.lost && !self.recover(rx)? {
+                return Ok(None);
+            }
+
+            match self.read_frame(rx) {
+                ReadResult::Frame(frame) => return Ok(Some(frame)),
+                ReadResult::Terminated | ReadResult::Finished => return Ok(None),
+                ReadResult::Failed(e) => return Err(e),
+                ReadResult::Lost => self.lost = true,
             }
-            Err(TryRecvError::Empty) => {}
         }
     }
 
-    decoder.send_eof()?;
+    fn stall_watch(&self) -> Option<Arc<StallWatch>> {
+        if self.is_device { Some(self.watch.clone()) } else { None }
+    }
 
-    Ok(())
+    fn reopen(&self) -> Option<Box<dyn FrameSource>> {
+        if !self.is_device {
+            return None;
+        }
+
+        // nothing is opened until the replacement reads its first frame, which starts by
+        // reconnecting as the stuck source still holds the device
+        let mut source = FfmpegSource::device(self.label.clone(), self.profile.clone(), self.format, self.window.clone());
+        source.lost = true;
+        Some(Box::new(source))
+    }
+
+    fn record(&mut self, recording: Recording) -> bool {
+        // recorder is created from the stream parameters once connected
+        self.recording = Some(recording);
+        true
+    }
+}
+
+impl Drop for FfmpegSource {
+    fn drop(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish();
+        }
+        if let Some(connection) = self.connection.as_mut() {
+            let _ = connection.decoder.send_eof();
+        }
+    }
 }
@@ -0,0 +1,115 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tauri::{Manager, Window};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::camera::CaptureProfile;
+use crate::detector::{DetectorKind, DetectorParams, Polarity};
+use crate::devices::camera_name;
+use crate::lens::LensCalibration;
+use crate::mapping::TargetMapping;
+use crate::orientation::Orientation;
+
+static CAMERAS_FILE: &str = "cameras.json";
+
+// settings persisted for each camera, keyed by camera id
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub profile: Option<CaptureProfile>,
+    pub polarity: Polarity,
+    pub detector: DetectorKind,
+    pub detector_params: DetectorParams,
+    pub orientation: Orientation,
+    // orientation was set by hand and is not inferred during calibration
+    pub orientation_locked: bool,
+    // inferred by the last calibration but not yet confirmed by another one
+    pub pending_orientation: Option<Orientation>,
+    pub lens: Option<LensCalibration>,
+    // measured from four points, replaces the mapping derived from calibration
+    pub mapping: Option<TargetMapping>,
+}
+
+// held while cameras.json is read, changed and written back, calibration updates it from its
+// camera thread while commands update it from the UI
+#[derive(Default)]
+pub struct CamerasLock(Mutex<()>);
+
+fn cameras_path(window: &Window) -> Option<PathBuf> {
+    window
+        .app_handle()
+        .path_resolver()
+        .app_dir()
+        .map(|dir| dir.join(CAMERAS_FILE))
+}
+
+fn load_all(window: &Window) -> HashMap<String, CameraConfig> {
+    let path = match cameras_path(window) {
+        Some(path) => path,
+        None => return HashMap::new(),
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            error!("Could not parse {:} ({:})", path.display(), e);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(), // nothing saved yet
+    }
+}
+
+pub fn load_camera_config(window: &Window, camera_id: &str) -> CameraConfig {
+    load_all(window).remove(camera_id).unwrap_or_default()
+}
+
+// writes all camera configs, through a temporary file so that readers never see a partly
+// written one
+fn save_all(path: &Path, configs: &HashMap<String, CameraConfig>) -> Result<(), anyhow::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_string_pretty(configs)?)?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+// update the persisted config of a camera in place
+pub fn update_camera_config<F: FnOnce(&mut CameraConfig)>(window: &Window, camera_id: &str, update: F) {
+    if let Err(e) = update_all(window, camera_id, update) {
+        error!("Could not save camera config for {:} ({:})", camera_id, e);
+    }
+}
+
+// read-modify-write of cameras.json under the lock
+fn update_all<F: FnOnce(&mut CameraConfig)>(window: &Window, camera_id: &str, update: F) -> Result<(), anyhow::Error> {
+    let path = cameras_path(window).ok_or_else(|| anyhow::Error::msg("Could not find app directory"))?;
+    let lock = window.state::<CamerasLock>();
+    let _guard = lock.0.lock().unwrap();
+
+    let mut configs = load_all(window);
+    update(configs.entry(camera_id.to_string()).or_default());
+    save_all(&path, &configs)?;
+    info!("Saved camera config for {:}", camera_id);
+
+    Ok(())
+}
+
+// profile to capture a camera with, a profile passed in by the UI is saved for next time
+pub fn resolve_capture_profile(window: &Window, camera_id: &str, profile: Option<CaptureProfile>) -> CaptureProfile {
+    match profile {
+        Some(profile) => {
+            let saved = profile.clone();
+            update_camera_config(window, camera_id, |config| config.profile = Some(saved));
+            profile
+        }
+        None => load_camera_config(window, camera_id)
+            .profile
+            .unwrap_or_else(|| CaptureProfile::default_for(camera_id, &camera_name(camera_id))),
+    }
+}
@@ -0,0 +1,104 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+// image controls of a camera, None leaves the control as it is. Values are in
+// the units of the driver, for UVC cameras exposure is in 100µs steps and white balance in kelvin
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CameraControls {
+    pub exposure: Option<i32>,
+    pub gain: Option<i32>,
+    pub white_balance: Option<i32>,
+    pub focus: Option<i32>,
+}
+
+// control ids from linux/v4l2-controls.h
+#[cfg(target_os = "linux")]
+static V4L2_CID_EXPOSURE_AUTO: u32 = 0x009a0901;
+#[cfg(target_os = "linux")]
+static V4L2_CID_EXPOSURE_ABSOLUTE: u32 = 0x009a0902;
+#[cfg(target_os = "linux")]
+static V4L2_CID_FOCUS_ABSOLUTE: u32 = 0x009a090a;
+#[cfg(target_os = "linux")]
+static V4L2_CID_FOCUS_AUTO: u32 = 0x009a090c;
+#[cfg(target_os = "linux")]
+static V4L2_CID_AUTO_WHITE_BALANCE: u32 = 0x0098090c;
+#[cfg(target_os = "linux")]
+static V4L2_CID_GAIN: u32 = 0x00980913;
+#[cfg(target_os = "linux")]
+static V4L2_CID_WHITE_BALANCE_TEMPERATURE: u32 = 0x0098091a;
+// value of V4L2_CID_EXPOSURE_AUTO for manual exposure
+#[cfg(target_os = "linux")]
+static V4L2_EXPOSURE_MANUAL: i32 = 1;
+// _IOWR('V', 28, struct v4l2_control)
+#[cfg(target_os = "linux")]
+static VIDIOC_S_CTRL: std::os::raw::c_ulong = 0xc008561c;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct V4l2Control {
+    id: u32,
+    value: i32,
+}
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn ioctl(fd: std::os::raw::c_int, request: std::os::raw::c_ulong, ...) -> std::os::raw::c_int;
+}
+
+// sets the controls of a v4l2 device, which can be opened again while ffmpeg captures from it
+#[cfg(target_os = "linux")]
+fn apply_v4l2_controls(device: &str, controls: &CameraControls) {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
+    let file = match OpenOptions::new().read(true).write(true).open(device) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Could not open {:} to set camera controls ({:})", device, e);
+            return;
+        }
+    };
+
+    // only controls the profile sets are touched, automatic modes are switched off before their
+    // manual value is set, in that order
+    let mut requested = Vec::new();
+    if let Some(exposure) = controls.exposure {
+        requested.extend([(V4L2_CID_EXPOSURE_AUTO, V4L2_EXPOSURE_MANUAL), (V4L2_CID_EXPOSURE_ABSOLUTE, exposure)]);
+    }
+    if let Some(gain) = controls.gain {
+        requested.push((V4L2_CID_GAIN, gain));
+    }
+    if let Some(white_balance) = controls.white_balance {
+        requested.extend([(V4L2_CID_AUTO_WHITE_BALANCE, 0), (V4L2_CID_WHITE_BALANCE_TEMPERATURE, white_balance)]);
+    }
+    if let Some(focus) = controls.focus {
+        requested.extend([(V4L2_CID_FOCUS_AUTO, 0), (V4L2_CID_FOCUS_ABSOLUTE, focus)]);
+    }
+
+    let set = |id: u32, value: i32| -> bool {
+        let mut control = V4l2Control { id, value };
+        unsafe { ioctl(file.as_raw_fd(), VIDIOC_S_CTRL, &mut control as *mut V4l2Control) == 0 }
+    };
+    for (id, value) in requested {
+        if !set(id, value) {
+            warn!("Could not set camera control {:#x} to {:} on {:} ({:})", id, value, device, std::io::Error::last_os_error());
+        }
+    }
+    info!("Applied camera controls {:?} to {:}", controls, device);
+}
+
+// applies the controls to a camera that was just opened
+pub fn apply_controls(label: &str, controls: &CameraControls) {
+    if *controls == CameraControls::default() {
+        return;
+    }
+
+    #[cfg(target_os = "linux")]
+    if crate::camera::is_v4l2_device(label) {
+        apply_v4l2_controls(label, controls);
+        return;
+    }
+
+    warn!("Camera controls are not supported for {:}, leaving them to the camera", label);
+}
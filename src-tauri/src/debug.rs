@@ -0,0 +1,223 @@
+use log::{error, info, warn};
+use opencv::core::{Point, Rect, Scalar, Vector};
+use opencv::imgcodecs::imwrite;
+use opencv::imgproc::{circle, cvt_color, put_text, rectangle, COLOR_GRAY2BGR, COLOR_RGB2BGR, FONT_HERSHEY_SIMPLEX, LINE_8};
+use opencv::prelude::*;
+use tauri::{Manager, Window};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::detector::Detection;
+
+static DEBUG_DIR: &str = "debug";
+// seconds between frames written for failed detections
+static MIN_INTERVAL: f64 = 1.0;
+// bytes all debug sessions together may take up
+static MAX_DEBUG_BYTES: u64 = 200 * 1024 * 1024;
+
+// folder holding a folder of debug frames for every session
+pub fn debug_dir(window: &Window) -> Option<PathBuf> {
+    window
+        .app_handle()
+        .path_resolver()
+        .app_dir()
+        .map(|dir| dir.join(DEBUG_DIR))
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    let mut size = 0;
+    for entry in entries.flatten() {
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => size += dir_size(&entry.path()),
+            Ok(metadata) => size += metadata.len(),
+            Err(_) => {}
+        }
+    }
+
+    return size;
+}
+
+// session folders other than `current`, oldest first
+fn old_sessions(root: &Path, current: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut sessions: Vec<(u64, PathBuf)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && path != current)
+        .filter_map(|path| {
+            let time = path.file_name()?.to_str()?.strip_prefix("session-")?.parse().ok()?;
+            Some((time, path))
+        })
+        .collect();
+    sessions.sort();
+
+    return sessions.into_iter().map(|(_, path)| path).collect();
+}
+
+// why a frame was written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugReason {
+    NoMarker,
+    MultipleMarkers,
+    ShotReset,
+}
+
+impl DebugReason {
+    fn name(&self) -> &'static str {
+        match self {
+            DebugReason::NoMarker => "no_marker",
+            DebugReason::MultipleMarkers => "multiple_markers",
+            DebugReason::ShotReset => "shot_reset",
+        }
+    }
+}
+
+// writes annotated frames of failed detections to a folder for the session
+pub struct DebugCapture {
+    root: PathBuf,
+    session_dir: PathBuf,
+    last_write: Option<Instant>,
+    // bytes taken up by all sessions including this one
+    total_bytes: u64,
+    frame_index: u32,
+    full: bool,
+}
+
+impl DebugCapture {
+    pub fn new(root: PathBuf) -> DebugCapture {
+        let session = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        let total_bytes = dir_size(&root);
+        DebugCapture {
+            session_dir: root.join(format!("session-{:}", session)),
+            root,
+            last_write: None,
+            total_bytes,
+            frame_index: 0,
+            full: false,
+        }
+    }
+
+    // deletes the oldest sessions until all of them fit in MAX_DEBUG_BYTES, false if this session
+    // alone is too big
+    fn make_space(&mut self) -> bool {
+        let mut sessions = old_sessions(&self.root, &self.session_dir).into_iter();
+        while self.total_bytes >= MAX_DEBUG_BYTES {
+            let session = match sessions.next() {
+                Some(session) => session,
+                None => return false,
+            };
+            let size = dir_size(&session);
+            match fs::remove_dir_all(&session) {
+                Ok(()) => {
+                    info!("Deleted old debug frames in {:}", session.display());
+                    self.total_bytes = self.total_bytes.saturating_sub(size);
+                }
+                Err(e) => error!("Could not delete {:} ({:})", session.display(), e),
+            }
+        }
+
+        return true;
+    }
+
+    fn annotate(frame: &Mat, roi: Rect, detections: &[Detection], thresholds: (u32, u32), reason: DebugReason) -> Result<Mat, opencv::Error> {
+        let mut annotated = Mat::default();
+        let code = if frame.channels() == 1 { COLOR_GRAY2BGR } else { COLOR_RGB2BGR };
+        cvt_color(frame, &mut annotated, code, 0)?;
+
+        // region searched for the aim in green, candidates in red
+        rectangle(&mut annotated, roi, Scalar::new(0.0, 255.0, 0.0, 0.0), 2, LINE_8, 0)?;
+        for detection in detections {
+            let center = Point::new(detection.x as i32, detection.y as i32);
+            circle(&mut annotated, center, (detection.size / 2.0) as i32, Scalar::new(0.0, 0.0, 255.0, 0.0), 2, LINE_8, 0)?;
+            let label = format!("{:.2}", detection.confidence);
+            put_text(&mut annotated, &label, center, FONT_HERSHEY_SIMPLEX, 0.5, Scalar::new(0.0, 0.0, 255.0, 0.0), 1, LINE_8, false)?;
+        }
+
+        let text = format!("{:} thresh {:}-{:} candidates {:}", reason.name(), thresholds.0, thresholds.1, detections.len());
+        put_text(&mut annotated, &text, Point::new(10, 25), FONT_HERSHEY_SIMPLEX, 0.7, Scalar::new(0.0, 255.0, 255.0, 0.0), 2, LINE_8, false)?;
+
+        Ok(annotated)
+    }
+
+    // writes the frame with the region searched for the aim, the candidate markers found in it
+    // (in pixels of the frame) and the thresholds used. Failed detections are rate limited, shot
+    // resets are always written. The oldest sessions are deleted to make space
+    pub fn capture(&mut self, frame: &Mat, roi: Rect, detections: &[Detection], thresholds: (u32, u32), reason: DebugReason) {
+        if self.full {
+            return;
+        }
+        if reason != DebugReason::ShotReset &&
+            self.last_write.map(|last_write| last_write.elapsed().as_secs_f64() < MIN_INTERVAL).unwrap_or(false)
+        {
+            return;
+        }
+        if !self.make_space() {
+            warn!("Debug frames of this session fill {:}, not writing more frames", self.root.display());
+            self.full = true;
+            return;
+        }
+        self.last_write = Some(Instant::now());
+
+        if let Err(e) = fs::create_dir_all(&self.session_dir) {
+            error!("Could not create {:} ({:})", self.session_dir.display(), e);
+            self.full = true;
+            return;
+        }
+
+        let annotated = match DebugCapture::annotate(frame, roi, detections, thresholds, reason) {
+            Ok(annotated) => annotated,
+            Err(e) => {
+                error!("Could not annotate debug frame ({:})", e);
+                return;
+            }
+        };
+
+        let path = self.session_dir.join(format!("{:06}_{:}.jpg", self.frame_index, reason.name()));
+        self.frame_index += 1;
+        match imwrite(&path.to_string_lossy(), &annotated, &Vector::new()) {
+            Ok(true) => self.total_bytes += fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0),
+            Ok(false) => error!("Could not write debug frame {:}", path.display()),
+            Err(e) => error!("Could not write debug frame {:} ({:})", path.display(), e),
+        }
+    }
+}
+
+fn zip_dir(zip: &mut ZipWriter<File>, dir: &Path, prefix: &str) -> zip::result::ZipResult<()> {
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = format!("{:}{:}", prefix, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            zip.add_directory(&name, FileOptions::default())?;
+            zip_dir(zip, &entry.path(), &format!("{:}/", name))?;
+        } else {
+            // frames are already compressed
+            zip.start_file(&name, FileOptions::default().compression_method(zip::CompressionMethod::Stored))?;
+            io::copy(&mut File::open(entry.path())?, zip)?;
+        }
+    }
+
+    Ok(())
+}
+
+// packs all debug sessions into a zip file at `path` to attach to a bug report
+pub fn write_debug_zip(root: &Path, path: &Path) -> zip::result::ZipResult<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    zip_dir(&mut zip, root, "")?;
+    zip.finish()?;
+
+    info!("Zipped debug frames to {:}", path.display());
+    Ok(())
+}
@@ -0,0 +1,329 @@
+use log::error;
+use opencv::core::{bitwise_not, no_array, sum_elems, KeyPoint, Point, Ptr, Rect, Size, Vector, BORDER_DEFAULT};
+use opencv::features2d::{SimpleBlobDetector, SimpleBlobDetector_Params};
+use opencv::imgproc::{arc_length, contour_area, cvt_color, find_contours, gaussian_blur, moments, threshold, CHAIN_APPROX_NONE, RETR_EXTERNAL, THRESH_BINARY_INV, THRESH_OTSU};
+use opencv::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::f64::consts::PI;
+
+// limits a marker has to be within, shared by all detectors
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct DetectorParams {
+    // in pixels
+    pub min_area: f64,
+    pub max_area: f64,
+    // 1 for a perfect circle
+    pub min_circularity: f64,
+    // ratio of the short to the long axis, 1 for a circle
+    pub min_inertia_ratio: f64,
+}
+
+impl Default for DetectorParams {
+    fn default() -> DetectorParams {
+        DetectorParams {
+            min_area: 450.0,
+            max_area: 10000.0,
+            min_circularity: 0.7,
+            min_inertia_ratio: 0.85,
+        }
+    }
+}
+
+// whether the aiming marker is darker or brighter than its background, IR illuminated
+// targets usually show a bright marker on a dark background
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Polarity {
+    Dark,
+    Bright,
+}
+
+impl Default for Polarity {
+    fn default() -> Polarity {
+        Polarity::Dark
+    }
+}
+
+// how markers are found in a frame
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DetectorKind {
+    // opencv's blob detector, centres are only as precise as its threshold steps
+    Blob,
+    // threshold and contours with a moment fit, centres are sub-pixel
+    Contour,
+}
+
+impl Default for DetectorKind {
+    fn default() -> DetectorKind {
+        DetectorKind::Blob
+    }
+}
+
+// marker found in a frame, in pixels of that frame
+#[derive(Clone, Copy, Debug)]
+pub struct Detection {
+    pub x: f64,
+    pub y: f64,
+    // diameter
+    pub size: f64,
+    // 0 to 1, from how round, large enough and contrasting the marker is
+    pub confidence: f64,
+}
+
+// finds markers in a blurred 8 bit gray frame with a dark marker
+pub trait MarkerDetector {
+    fn detect(&mut self, gray: &Mat) -> Vec<Detection>;
+
+    fn set_thresholds(&mut self, min_thresh: u32, max_thresh: u32);
+}
+
+fn get_circle_detector(params: &DetectorParams, min_thresh: u32, max_thresh: u32) -> Ptr<SimpleBlobDetector> {
+    let mut blob_params = SimpleBlobDetector_Params::default().unwrap();
+    blob_params.min_threshold = min_thresh as f32;
+    blob_params.max_threshold = max_thresh as f32;
+
+    blob_params.filter_by_color = false;
+    blob_params.filter_by_convexity = false;
+
+    blob_params.filter_by_area = true;
+    blob_params.min_area = params.min_area as f32;
+    blob_params.max_area = params.max_area as f32;
+
+    blob_params.filter_by_circularity = true;
+    blob_params.min_circularity = params.min_circularity as f32;
+
+    blob_params.filter_by_inertia = true;
+    blob_params.min_inertia_ratio = params.min_inertia_ratio as f32;
+    return SimpleBlobDetector::create(blob_params).unwrap();
+}
+
+// blurred 8 bit gray frame with a dark marker, whatever the frame format and marker polarity
+pub fn prepare_frame(frame: &Mat, polarity: Polarity) -> opencv::Result<Mat> {
+    let mut gray = Mat::default();
+    let mut inverted = Mat::default();
+    let mut gray_frame = frame;
+
+    // if frame is not grayscale, convert it
+    if gray_frame.channels() == 3 {
+        cvt_color(gray_frame, &mut gray, opencv::imgproc::COLOR_RGB2GRAY, 0)?;
+        gray_frame = &gray;
+    }
+
+    // thresholds are tuned for a dark marker, turn a bright one dark
+    if polarity == Polarity::Bright {
+        bitwise_not(gray_frame, &mut inverted, &no_array())?;
+        gray_frame = &inverted;
+    }
+
+    let mut blurred_frame = Mat::default();
+    gaussian_blur(gray_frame, &mut blurred_frame, Size{width: 9, height: 9}, 0.0, 0.0, BORDER_DEFAULT)?;
+
+    Ok(blurred_frame)
+}
+
+fn clip(rect: Rect, gray: &Mat) -> Rect {
+    let x = rect.x.max(0);
+    let y = rect.y.max(0);
+    let width = (rect.x + rect.width).min(gray.cols()) - x;
+    let height = (rect.y + rect.height).min(gray.rows()) - y;
+    return Rect::new(x, y, width.max(0), height.max(0));
+}
+
+fn sum_rect(gray: &Mat, rect: Rect) -> (f64, f64) {
+    if rect.width == 0 || rect.height == 0 {
+        return (0.0, 0.0);
+    }
+
+    match Mat::roi(gray, rect).and_then(|roi| sum_elems(&roi)) {
+        Ok(sum) => (sum[0], (rect.width * rect.height) as f64),
+        Err(_) => (0.0, 0.0),
+    }
+}
+
+// mean gray level inside a marker and in a ring around it, None if either is outside the frame
+pub fn marker_levels(gray: &Mat, x: f64, y: f64, size: f64) -> Option<(f64, f64)> {
+    let square = |half: f64| clip(Rect::new((x - half) as i32, (y - half) as i32, (2.0 * half) as i32, (2.0 * half) as i32), gray);
+
+    // square inside the marker, the square around it and a wider one around that
+    let (inner_sum, inner_area) = sum_rect(gray, square(0.35 * size));
+    let (bound_sum, bound_area) = sum_rect(gray, square(0.5 * size));
+    let (outer_sum, outer_area) = sum_rect(gray, square(0.8 * size));
+    if inner_area == 0.0 || outer_area <= bound_area {
+        return None;
+    }
+
+    return Some((inner_sum / inner_area, (outer_sum - bound_sum) / (outer_area - bound_area)));
+}
+
+// how much darker the inside of a marker is than a ring around it, 0 to 1
+fn marker_contrast(gray: &Mat, x: f64, y: f64, size: f64) -> f64 {
+    match marker_levels(gray, x, y, size) {
+        Some((inside, around)) => ((around - inside) / 255.0).max(0.0).min(1.0),
+        None => 0.0,
+    }
+}
+
+// opencv's blob detector
+pub struct BlobDetector {
+    params: DetectorParams,
+    detector: Ptr<SimpleBlobDetector>,
+}
+
+impl BlobDetector {
+    pub fn new(params: DetectorParams, min_thresh: u32, max_thresh: u32) -> BlobDetector {
+        BlobDetector { params, detector: get_circle_detector(&params, min_thresh, max_thresh) }
+    }
+}
+
+impl MarkerDetector for BlobDetector {
+    fn detect(&mut self, gray: &Mat) -> Vec<Detection> {
+        let mut keypoints: Vector<KeyPoint> = Vector::new();
+        if let Err(e) = self.detector.detect(gray, &mut keypoints, &no_array()) {
+            error!("Could not detect blobs ({:})", e);
+        }
+
+        // blobs already passed the circularity, inertia and area filters, only contrast is left to score
+        return keypoints
+            .iter()
+            .map(|keypoint| {
+                let (x, y, size) = (keypoint.pt.x as f64, keypoint.pt.y as f64, keypoint.size as f64);
+                Detection { x, y, size, confidence: marker_contrast(gray, x, y, size) }
+            })
+            .collect();
+    }
+
+    fn set_thresholds(&mut self, min_thresh: u32, max_thresh: u32) {
+        self.detector = get_circle_detector(&self.params, min_thresh, max_thresh);
+    }
+}
+
+// threshold and contours with a moment fit
+pub struct ContourDetector {
+    params: DetectorParams,
+    min_thresh: u32,
+    max_thresh: u32,
+}
+
+impl ContourDetector {
+    pub fn new(params: DetectorParams, min_thresh: u32, max_thresh: u32) -> ContourDetector {
+        ContourDetector { params, min_thresh, max_thresh }
+    }
+}
+
+impl MarkerDetector for ContourDetector {
+    fn detect(&mut self, gray: &Mat) -> Vec<Detection> {
+        match detect_contours(gray, &self.params, self.min_thresh, self.max_thresh) {
+            Ok(detections) => detections,
+            Err(e) => {
+                error!("Could not detect contours ({:})", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn set_thresholds(&mut self, min_thresh: u32, max_thresh: u32) {
+        self.min_thresh = min_thresh;
+        self.max_thresh = max_thresh;
+    }
+}
+
+fn detect_contours(gray: &Mat, params: &DetectorParams, min_thresh: u32, max_thresh: u32) -> Result<Vec<Detection>, opencv::Error> {
+    // otsu finds the threshold between marker and background, kept within the configured range
+    let mut binary = Mat::default();
+    let otsu = threshold(gray, &mut binary, 0.0, 255.0, THRESH_BINARY_INV | THRESH_OTSU)?;
+    let thresh = otsu.max(min_thresh as f64).min(max_thresh as f64);
+    if thresh != otsu {
+        threshold(gray, &mut binary, thresh, 255.0, THRESH_BINARY_INV)?;
+    }
+
+    let mut contours: Vector<Vector<Point>> = Vector::new();
+    find_contours(&binary, &mut contours, RETR_EXTERNAL, CHAIN_APPROX_NONE, Point::new(0, 0))?;
+
+    let mut detections = Vec::new();
+    for contour in contours.iter() {
+        let area = contour_area(&contour, false)?;
+        if area < params.min_area || area > params.max_area {
+            continue;
+        }
+
+        let perimeter = arc_length(&contour, true)?;
+        let circularity = 4.0 * PI * area / (perimeter * perimeter);
+        if circularity < params.min_circularity {
+            continue;
+        }
+
+        // moments of the outline polygon give a sub-pixel centre, their central moments how
+        // elongated it is
+        let m = moments(&contour, false)?;
+        if m.m00 <= 0.0 {
+            continue;
+        }
+        let half_sum = (m.mu20 + m.mu02) / 2.0;
+        let half_diff = (((m.mu20 - m.mu02) / 2.0).powi(2) + m.mu11 * m.mu11).sqrt();
+        let inertia_ratio = if half_sum + half_diff > 0.0 { (half_sum - half_diff) / (half_sum + half_diff) } else { 0.0 };
+        if inertia_ratio < params.min_inertia_ratio {
+            continue;
+        }
+
+        let x = m.m10 / m.m00;
+        let y = m.m01 / m.m00;
+        let size = 2.0 * (area / PI).sqrt();
+        let area_score = ((area - params.min_area) / params.min_area.max(1.0)).min(1.0);
+        let confidence = circularity.min(1.0) * inertia_ratio * area_score * marker_contrast(gray, x, y, size);
+        detections.push(Detection { x, y, size, confidence });
+    }
+
+    Ok(detections)
+}
+
+pub fn create_detector(kind: DetectorKind, params: DetectorParams, min_thresh: u32, max_thresh: u32) -> Box<dyn MarkerDetector> {
+    match kind {
+        DetectorKind::Blob => Box::new(BlobDetector::new(params, min_thresh, max_thresh)),
+        DetectorKind::Contour => Box::new(ContourDetector::new(params, min_thresh, max_thresh)),
+    }
+}
+
+// finds markers with the chosen detector in frames of any format and marker polarity
+pub struct Detector {
+    polarity: Polarity,
+    thresholds: (u32, u32),
+    marker_detector: Box<dyn MarkerDetector>,
+}
+
+impl Detector {
+    pub fn new(kind: DetectorKind, params: DetectorParams, polarity: Polarity, min_thresh: u32, max_thresh: u32) -> Detector {
+        Detector { polarity, thresholds: (min_thresh, max_thresh), marker_detector: create_detector(kind, params, min_thresh, max_thresh) }
+    }
+
+    pub fn thresholds(&self) -> (u32, u32) {
+        self.thresholds
+    }
+
+    pub fn set_thresholds(&mut self, min_thresh: u32, max_thresh: u32) {
+        self.thresholds = (min_thresh, max_thresh);
+        self.marker_detector.set_thresholds(min_thresh, max_thresh);
+    }
+
+    pub fn detect(&mut self, frame: &Mat) -> Vec<Detection> {
+        match self.prepare(frame) {
+            Ok(gray) => self.marker_detector.detect(&gray),
+            Err(e) => {
+                error!("Could not prepare frame for detection ({:})", e);
+                Vec::new()
+            }
+        }
+    }
+
+    // frame as the detector sees it, an 8 bit gray frame with a dark marker
+    pub fn prepare(&self, frame: &Mat) -> opencv::Result<Mat> {
+        prepare_frame(frame, self.polarity)
+    }
+
+    // markers in a frame that was already prepared
+    pub fn detect_prepared(&mut self, gray: &Mat) -> Vec<Detection> {
+        self.marker_detector.detect(gray)
+    }
+}
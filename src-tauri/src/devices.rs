@@ -0,0 +1,225 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use ffmpeg::ffi::*;
+use ffmpeg::Dictionary;
+use log::{info, warn};
+use serde::Serialize;
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use crate::camera::{camera_format_name, open_camera};
+
+// modes probed on each camera in addition to its default mode
+// (width, height, framerate, input format)
+static PROBE_MODES: [(u32, u32, u32, &str); 6] = [
+    (1280, 720, 120, "mjpeg"),
+    (1280, 720, 60, "mjpeg"),
+    (1280, 720, 30, ""),
+    (800, 600, 90, "mjpeg"),
+    (800, 600, 30, ""),
+    (640, 480, 30, ""),
+];
+
+// sample rates reported when a mic supports a continuous range
+static SAMPLE_RATES: [u32; 9] = [8000, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 192000];
+
+#[derive(Serialize, Clone, PartialEq)]
+pub struct CameraMode {
+    pub width: u32,
+    pub height: u32,
+    pub framerate: f64,
+    pub pixel_format: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CameraInfo {
+    pub id: String,
+    pub name: String,
+    pub modes: Vec<CameraMode>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct MicMode {
+    pub channels: u16,
+    pub sample_rates: Vec<u32>,
+    pub sample_format: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct MicInfo {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+    pub modes: Vec<MicMode>,
+}
+
+// list (device name, description) of video input sources known to ffmpeg
+fn list_video_sources() -> Vec<(String, String)> {
+    let mut sources = Vec::new();
+    let format_name = match camera_format_name() {
+        Some(name) => CString::new(name).unwrap(),
+        None => return sources,
+    };
+
+    unsafe {
+        let fmt = av_find_input_format(format_name.as_ptr());
+        let mut list = ptr::null_mut();
+        let res = avdevice_list_input_sources(fmt, ptr::null(), ptr::null_mut(), &mut list);
+        if res < 0 || list.is_null() {
+            warn!("Could not list video sources ({:})", ffmpeg::Error::from(res));
+        } else {
+            for i in 0..(*list).nb_devices as isize {
+                let device = *(*list).devices.offset(i);
+                let name = CStr::from_ptr((*device).device_name).to_string_lossy().into_owned();
+                let description = CStr::from_ptr((*device).device_description).to_string_lossy().into_owned();
+                sources.push((name, description));
+            }
+        }
+        avdevice_free_list_devices(&mut list);
+    }
+
+    if sources.is_empty() && cfg!(target_os = "linux") {
+        // older v4l2 builds do not implement device listing
+        if let Ok(entries) = std::fs::read_dir("/dev") {
+            let mut paths: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().to_string_lossy().into_owned())
+                .filter(|path| path.starts_with("/dev/video"))
+                .collect();
+            paths.sort();
+            for path in paths {
+                sources.push((path.clone(), path));
+            }
+        }
+    }
+
+    return sources;
+}
+
+// open the camera with the given options and read back the mode it actually chose
+fn probe_mode(id: &str, options: Dictionary) -> Option<CameraMode> {
+    let input = open_camera(id, options, None).ok()?;
+    let stream = input.streams().best(ffmpeg::media::Type::Video)?;
+    let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters()).ok()?;
+    let decoder = context.decoder().video().ok()?;
+
+    let rate = stream.avg_frame_rate();
+    let framerate = if rate.denominator() != 0 { f64::from(rate) } else { 0.0 };
+    let pixel_format = match decoder.id() {
+        ffmpeg::codec::Id::RAWVIDEO => format!("{:?}", decoder.format()),
+        id => format!("{:?}", id),
+    }.to_lowercase();
+
+    Some(CameraMode { width: decoder.width(), height: decoder.height(), framerate, pixel_format })
+}
+
+fn probe_camera_modes(id: &str) -> Vec<CameraMode> {
+    let mut modes: Vec<CameraMode> = Vec::new();
+    if let Some(mode) = probe_mode(id, Dictionary::new()) {
+        modes.push(mode);
+    }
+
+    for (width, height, framerate, input_format) in PROBE_MODES.iter() {
+        let mut options = Dictionary::new();
+        options.set("video_size", &format!("{:}x{:}", width, height));
+        options.set("framerate", &framerate.to_string());
+        if !input_format.is_empty() {
+            if cfg!(target_os = "linux") {
+                options.set("input_format", input_format);
+            } else {
+                options.set("vcodec", input_format);
+            }
+        }
+
+        // devices silently fall back to another mode so only keep modes that match the request
+        if let Some(mode) = probe_mode(id, options) {
+            let matches = mode.width == *width && mode.height == *height;
+            if matches && !modes.contains(&mode) {
+                modes.push(mode);
+            }
+        }
+    }
+
+    return modes;
+}
+
+// friendly name of the camera opened by `id`, the id itself if it is not listed
+pub fn camera_name(id: &str) -> String {
+    list_video_sources()
+        .into_iter()
+        .find(|(device_name, _)| device_name == id)
+        .map(|(_, description)| description)
+        .unwrap_or_else(|| id.to_string())
+}
+
+#[tauri::command]
+pub async fn list_cameras(probe: Option<bool>) -> Vec<CameraInfo> {
+    let probe = probe.unwrap_or(true);
+    let mut cameras = Vec::new();
+    for (device_name, description) in list_video_sources() {
+        // the device name stays the same when cameras share a friendly name, dshow accepts its
+        // "@device_pnp_..." moniker after "video=" just like the friendly name. The UI shows the
+        // friendly name and only opens cameras by id
+        let id = device_name;
+        let modes = if probe { probe_camera_modes(&id) } else { Vec::new() };
+        info!("Found camera {:} ({:} modes)", id, modes.len());
+        cameras.push(CameraInfo { id, name: description, modes });
+    }
+
+    return cameras;
+}
+
+#[tauri::command]
+pub async fn list_mics() -> Vec<MicInfo> {
+    let host = cpal::default_host();
+    let default_name = host.default_input_device().and_then(|device| device.name().ok());
+
+    let devices = match host.input_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            warn!("Could not list mics ({:})", e);
+            return Vec::new();
+        }
+    };
+
+    let mut mics = Vec::new();
+    for device in devices {
+        let name = match device.name() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        let mut modes = Vec::new();
+        if let Ok(configs) = device.supported_input_configs() {
+            for config in configs {
+                let min_rate = config.min_sample_rate().0;
+                let max_rate = config.max_sample_rate().0;
+                let mut sample_rates: Vec<u32> = SAMPLE_RATES
+                    .iter()
+                    .copied()
+                    .filter(|rate| *rate >= min_rate && *rate <= max_rate)
+                    .collect();
+                if !sample_rates.contains(&min_rate) {
+                    sample_rates.insert(0, min_rate);
+                }
+                if !sample_rates.contains(&max_rate) {
+                    sample_rates.push(max_rate);
+                }
+
+                modes.push(MicMode {
+                    channels: config.channels(),
+                    sample_rates,
+                    sample_format: format!("{:?}", config.sample_format()).to_lowercase(),
+                });
+            }
+        }
+
+        info!("Found mic {:} ({:} modes)", name, modes.len());
+        let is_default = default_name.as_ref() == Some(&name);
+        mics.push(MicInfo { id: name.clone(), name, is_default, modes });
+    }
+
+    return mics;
+}
@@ -0,0 +1,353 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use ffmpeg::Error;
+use log::error;
+use opencv::core::{mean, mean_std_dev, no_array, Rect, BORDER_DEFAULT, CV_64F};
+use opencv::imgproc::{cvt_color, laplacian, COLOR_RGB2GRAY};
+use opencv::prelude::*;
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::Arc;
+
+use crate::camera::StallWatch;
+use crate::detector::{marker_levels, Detection};
+use crate::recorder::Recording;
+use crate::source::{Frame, FrameSource};
+
+// seconds between diagnostics reports
+static REPORT_INTERVAL: f64 = 1.0;
+// seconds of frame brightness flicker is looked for in
+static FLICKER_WINDOW: f64 = 2.0;
+// bins of the reported histogram
+static HISTOGRAM_BINS: usize = 64;
+// gray levels this close to black or white count as clipped
+static CLIP_MARGIN: u8 = 2;
+// beats slower than this are taken for lighting changes rather than flicker, in Hz
+static MIN_BEAT: f64 = 0.5;
+// brightness samples waiting for the collector, newer ones are dropped beyond this
+pub static BRIGHTNESS_CAPACITY: usize = 1024;
+
+// pass and warn limits of each check
+static FOCUS_LIMITS: (f64, f64) = (100.0, 30.0);
+static CLIPPING_LIMITS: (f64, f64) = (1.0, 5.0);
+static CONTRAST_LIMITS: (f64, f64) = (60.0, 25.0);
+static FLICKER_LIMITS: (f64, f64) = (1.0, 3.0);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+// a measured value and its verdict, a value that could not be measured fails
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Check {
+    pub value: Option<f64>,
+    pub verdict: Verdict,
+}
+
+impl Check {
+    fn higher_is_better(value: Option<f64>, (pass, warn): (f64, f64)) -> Check {
+        let verdict = match value {
+            Some(value) if value >= pass => Verdict::Pass,
+            Some(value) if value >= warn => Verdict::Warn,
+            _ => Verdict::Fail,
+        };
+        Check { value, verdict }
+    }
+
+    fn lower_is_better(value: Option<f64>, (pass, warn): (f64, f64)) -> Check {
+        let verdict = match value {
+            Some(value) if value <= pass => Verdict::Pass,
+            Some(value) if value <= warn => Verdict::Warn,
+            _ => Verdict::Fail,
+        };
+        Check { value, verdict }
+    }
+}
+
+// image quality of the camera, sent with `camera_diagnostics`
+#[derive(Serialize, Clone, Debug)]
+pub struct Diagnostics {
+    // variance of the laplacian on the marker
+    pub focus: Check,
+    // percent of pixels at black or white
+    pub clipping: Check,
+    // gray levels between the marker and its background
+    pub contrast: Check,
+    // brightness oscillation from mains powered lighting in percent of the mean brightness
+    pub flicker: Check,
+    // mains frequency the flicker matches, 50 or 60
+    pub flicker_mains_hz: Option<u32>,
+    // share of pixels in each of evenly spaced gray level bins
+    pub histogram: Vec<f64>,
+}
+
+fn to_gray(frame: &Mat) -> Option<Mat> {
+    if frame.channels() == 1 {
+        return Some(frame.clone());
+    }
+
+    let mut gray = Mat::default();
+    match cvt_color(frame, &mut gray, COLOR_RGB2GRAY, 0) {
+        Ok(()) => Some(gray),
+        Err(e) => {
+            error!("Could not convert frame for diagnostics ({:})", e);
+            None
+        }
+    }
+}
+
+fn focus(gray: &Mat, marker: &Detection) -> Option<f64> {
+    // square around the marker including its edge
+    let half = 0.8 * marker.size;
+    let x = ((marker.x - half) as i32).max(0);
+    let y = ((marker.y - half) as i32).max(0);
+    let width = ((marker.x + half) as i32).min(gray.cols()) - x;
+    let height = ((marker.y + half) as i32).min(gray.rows()) - y;
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+
+    let region = Mat::roi(gray, Rect::new(x, y, width, height)).ok()?;
+    let mut edges = Mat::default();
+    laplacian(&region, &mut edges, CV_64F, 1, 1.0, 0.0, BORDER_DEFAULT).ok()?;
+    let mut edges_mean = Mat::default();
+    let mut edges_std_dev = Mat::default();
+    mean_std_dev(&edges, &mut edges_mean, &mut edges_std_dev, &no_array()).ok()?;
+    let std_dev = *edges_std_dev.at::<f64>(0).ok()?;
+
+    return Some(std_dev * std_dev);
+}
+
+// histogram of the gray levels and the percent of clipped pixels
+fn histogram(gray: &Mat) -> Option<(Vec<f64>, f64)> {
+    let data = gray.data_bytes().ok()?;
+    if data.is_empty() {
+        return None;
+    }
+
+    let mut bins = vec![0.0; HISTOGRAM_BINS];
+    let mut clipped = 0;
+    for level in data.iter() {
+        bins[*level as usize * HISTOGRAM_BINS / 256] += 1.0;
+        if *level <= CLIP_MARGIN || *level >= 255 - CLIP_MARGIN {
+            clipped += 1;
+        }
+    }
+
+    let n = data.len() as f64;
+    return Some((bins.iter().map(|count| count / n).collect(), 100.0 * clipped as f64 / n));
+}
+
+// amplitude of the brightness at `frequency` Hz in percent of the mean
+fn oscillation(brightness: &VecDeque<(f64, f64)>, frequency: f64) -> f64 {
+    let n = brightness.len() as f64;
+    let mean = brightness.iter().map(|(_, level)| level).sum::<f64>() / n;
+    if mean <= 0.0 {
+        return 0.0;
+    }
+
+    let (mut re, mut im) = (0.0, 0.0);
+    for (time, level) in brightness.iter() {
+        let phase = 2.0 * PI * frequency * time;
+        re += (level - mean) * phase.cos();
+        im -= (level - mean) * phase.sin();
+    }
+
+    return 100.0 * 2.0 * re.hypot(im) / n / mean;
+}
+
+// frequency a light flickering at `frequency` Hz shows at when sampled at `fps`
+fn alias(frequency: f64, fps: f64) -> f64 {
+    let folded = frequency % fps;
+    return folded.min(fps - folded);
+}
+
+// flicker in percent and the mains frequency it matches, lights flicker at twice the mains
+// frequency and show up in the frames at the beat with the frame rate
+fn flicker(brightness: &VecDeque<(f64, f64)>) -> Option<(f64, Option<u32>)> {
+    let duration = brightness.back()?.0 - brightness.front()?.0;
+    if brightness.len() < 3 || duration < FLICKER_WINDOW / 2.0 {
+        return None;
+    }
+    let fps = (brightness.len() - 1) as f64 / duration;
+
+    let mut strongest = (0.0, None);
+    for mains_hz in [50, 60] {
+        let beat = alias(2.0 * mains_hz as f64, fps);
+        if beat < MIN_BEAT {
+            continue; // the frame rate is locked to this mains frequency, it cannot flicker
+        }
+
+        let amplitude = oscillation(brightness, beat);
+        if amplitude > strongest.0 {
+            strongest = (amplitude, Some(mains_hz));
+        }
+    }
+
+    return Some(strongest);
+}
+
+// mean gray level of a frame
+fn brightness(frame: &Mat) -> Option<f64> {
+    match mean(frame, &no_array()) {
+        Ok(level) => Some((0..frame.channels() as usize).map(|channel| level[channel]).sum::<f64>() / frame.channels() as f64),
+        Err(e) => {
+            error!("Could not measure frame brightness ({:})", e);
+            None
+        }
+    }
+}
+
+// measures the brightness of every frame as it is read, on the capture thread, flicker is only
+// seen at the capture frame rate and processing drops frames when it cannot keep up
+pub struct BrightnessSource {
+    source: Box<dyn FrameSource>,
+    brightness_tx: SyncSender<(f64, f64)>,
+}
+
+impl BrightnessSource {
+    pub fn new(source: Box<dyn FrameSource>, brightness_tx: SyncSender<(f64, f64)>) -> BrightnessSource {
+        BrightnessSource { source, brightness_tx }
+    }
+}
+
+impl FrameSource for BrightnessSource {
+    fn next_frame(&mut self, rx: &Receiver<()>) -> Result<Option<Frame>, Error> {
+        let frame = self.source.next_frame(rx)?;
+        if let Some(frame) = frame.as_ref() {
+            if let Some(level) = brightness(&frame.mat) {
+                // a full channel means diagnostics are not being collected
+                let _ = self.brightness_tx.try_send((frame.time.pts, level));
+            }
+        }
+        return Ok(frame);
+    }
+
+    fn record(&mut self, recording: Recording) -> bool {
+        self.source.record(recording)
+    }
+
+    fn stall_watch(&self) -> Option<Arc<StallWatch>> {
+        self.source.stall_watch()
+    }
+
+    fn reopen(&self) -> Option<Box<dyn FrameSource>> {
+        let source = self.source.reopen()?;
+        Some(Box::new(BrightnessSource::new(source, self.brightness_tx.clone())))
+    }
+}
+
+// collects frame brightness from a BrightnessSource and reports diagnostics at intervals
+pub struct DiagnosticsCollector {
+    brightness_rx: Receiver<(f64, f64)>,
+    // capture time and mean gray level of recent frames
+    brightness: VecDeque<(f64, f64)>,
+    last_report: Option<f64>,
+}
+
+impl DiagnosticsCollector {
+    pub fn new(brightness_rx: Receiver<(f64, f64)>) -> DiagnosticsCollector {
+        DiagnosticsCollector { brightness_rx, brightness: VecDeque::new(), last_report: None }
+    }
+
+    // takes in the brightness of the frames captured since the last call
+    fn collect(&mut self) {
+        for (time, level) in self.brightness_rx.try_iter() {
+            self.brightness.push_back((time, level));
+            while self.brightness.front().map(|(first, _)| time - first > FLICKER_WINDOW).unwrap_or(false) {
+                self.brightness.pop_front();
+            }
+        }
+    }
+
+    // diagnostics of `frame` with the marker found in it and, as `prepared`, the frame as the
+    // detector sees it, None until the next report is due
+    pub fn report(&mut self, frame: &Mat, prepared: &Mat, marker: Option<Detection>, time: f64) -> Option<Diagnostics> {
+        self.collect();
+        if self.last_report.map(|last_report| time - last_report < REPORT_INTERVAL).unwrap_or(false) {
+            return None;
+        }
+        self.last_report = Some(time);
+
+        let gray = to_gray(frame)?;
+        let (histogram, clipping) = match histogram(&gray) {
+            Some((histogram, clipping)) => (histogram, Some(clipping)),
+            None => (Vec::new(), None),
+        };
+        let focus = marker.and_then(|marker| focus(&gray, &marker));
+        let contrast = marker
+            .and_then(|marker| marker_levels(prepared, marker.x, marker.y, marker.size))
+            .map(|(inside, around)| around - inside);
+        let (flicker, flicker_mains_hz) = match flicker(&self.brightness) {
+            Some((flicker, mains_hz)) => (Some(flicker), mains_hz),
+            None => (None, None),
+        };
+
+        return Some(Diagnostics {
+            focus: Check::higher_is_better(focus, FOCUS_LIMITS),
+            clipping: Check::lower_is_better(clipping, CLIPPING_LIMITS),
+            contrast: Check::higher_is_better(contrast, CONTRAST_LIMITS),
+            flicker: Check::lower_is_better(flicker, FLICKER_LIMITS),
+            flicker_mains_hz: if flicker.map(|flicker| flicker > FLICKER_LIMITS.0).unwrap_or(false) { flicker_mains_hz } else { None },
+            histogram,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FrameTime;
+    use opencv::core::{Scalar, CV_8UC1};
+    use std::sync::mpsc::{channel, sync_channel};
+    use std::time::Instant;
+
+    // uniform frames lit by a light flickering at `flicker_hz`
+    struct FlickeringSource {
+        fps: f64,
+        flicker_hz: f64,
+        index: u32,
+    }
+
+    impl FrameSource for FlickeringSource {
+        fn next_frame(&mut self, _rx: &Receiver<()>) -> Result<Option<Frame>, Error> {
+            let pts = self.index as f64 / self.fps;
+            self.index += 1;
+            let level = 128.0 + 20.0 * (2.0 * PI * self.flicker_hz * pts).sin();
+            let mat = Mat::new_rows_cols_with_default(8, 8, CV_8UC1, Scalar::all(level)).unwrap();
+            Ok(Some(Frame { mat, time: FrameTime { pts, instant: Instant::now() } }))
+        }
+    }
+
+    #[test]
+    fn flicker_is_measured_at_the_capture_frame_rate() {
+        // 50Hz mains lighting flickers at 100Hz and beats at 20Hz with a 120fps camera
+        let (brightness_tx, brightness_rx) = sync_channel(BRIGHTNESS_CAPACITY);
+        let mut source = BrightnessSource::new(Box::new(FlickeringSource { fps: 120.0, flicker_hz: 100.0, index: 0 }), brightness_tx);
+        let mut collector = DiagnosticsCollector::new(brightness_rx);
+        let (_tx, rx) = channel();
+
+        // processing only keeps up with every third frame, at 40fps every sample would land on a
+        // zero crossing of the flicker
+        let mut processed = Vec::new();
+        for index in 0..240 {
+            let frame = source.next_frame(&rx).unwrap().unwrap();
+            if index % 3 == 0 {
+                processed.push(frame);
+            }
+        }
+
+        let last = processed.last().unwrap();
+        let diagnostics = collector.report(&last.mat, &last.mat, None, last.time.pts).unwrap();
+        assert!(diagnostics.flicker.value.unwrap() > 10.0, "flicker of {:?}", diagnostics.flicker.value);
+        assert_eq!(diagnostics.flicker.verdict, Verdict::Fail);
+        assert_eq!(diagnostics.flicker_mains_hz, Some(50));
+    }
+}
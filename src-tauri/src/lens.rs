@@ -0,0 +1,265 @@
+use log::{error, info, warn};
+use opencv::calib3d::{calibrate_camera, find_chessboard_corners, undistort_points, CALIB_CB_ADAPTIVE_THRESH, CALIB_CB_FAST_CHECK, CALIB_CB_NORMALIZE_IMAGE};
+use opencv::core::{no_array, Point2f, Point3f, Size, TermCriteria, TermCriteria_Type, Vector, CV_64F};
+use opencv::imgproc::corner_sub_pix;
+use opencv::prelude::*;
+use serde::{Deserialize, Serialize};
+use tauri::Window;
+
+use std::sync::mpsc::Receiver;
+
+use crate::config::update_camera_config;
+use crate::source::{camera_stream, FrameSource, FrameTime};
+
+// views of the board needed for a calibration
+static REQUIRED_VIEWS: usize = 15;
+// seconds between attempts to find the board, finding it in a full frame is slow
+static DETECT_INTERVAL: f64 = 0.5;
+// mean distance in pixels the board has to move before another view of it is taken
+static MIN_VIEW_CHANGE: f64 = 40.0;
+// seconds after which the wizard gives up
+static LENS_TIMEOUT: f64 = 300.0;
+
+// intrinsics and distortion coefficients of a camera, as found by calibrate_camera
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LensCalibration {
+    // frame size the calibration was made at, it does not apply to other sizes
+    pub image_size: [i32; 2],
+    pub camera_matrix: [[f64; 3]; 3],
+    pub dist_coeffs: Vec<f64>,
+    // reprojection error in pixels
+    pub rms: f64,
+}
+
+impl LensCalibration {
+    pub fn undistorter(&self) -> Result<Undistorter, opencv::Error> {
+        Ok(Undistorter {
+            image_size: self.image_size,
+            camera_matrix: Mat::from_slice_2d(&self.camera_matrix)?,
+            dist_coeffs: Mat::from_slice(&self.dist_coeffs)?.clone(),
+        })
+    }
+}
+
+// undistorts single points, whole frames are never remapped
+pub struct Undistorter {
+    image_size: [i32; 2],
+    camera_matrix: Mat,
+    dist_coeffs: Mat,
+}
+
+impl Undistorter {
+    fn fits(&self, frame: &Mat) -> bool {
+        self.image_size == [frame.cols(), frame.rows()]
+    }
+
+    // pixel position the point would have without lens distortion, keeping the camera matrix
+    pub fn undistort(&self, point: (f64, f64)) -> (f64, f64) {
+        let src = Vector::<Point2f>::from_slice(&[Point2f::new(point.0 as f32, point.1 as f32)]);
+        let mut dst = Vector::<Point2f>::new();
+        match undistort_points(&src, &mut dst, &self.camera_matrix, &self.dist_coeffs, &no_array(), &self.camera_matrix) {
+            Ok(()) => match dst.get(0) {
+                Ok(undistorted) => (undistorted.x as f64, undistorted.y as f64),
+                Err(_) => point,
+            },
+            Err(e) => {
+                error!("Could not undistort point ({:})", e);
+                point
+            }
+        }
+    }
+}
+
+// drops the undistorter if the frames of the stream are not the size it was calibrated at
+pub fn check_undistorter(undistorter: &mut Option<Undistorter>, frame: &Mat) {
+    if undistorter.as_ref().map(|undistorter| !undistorter.fits(frame)).unwrap_or(false) {
+        warn!("Lens calibration was made at another frame size, not undistorting");
+        *undistorter = None;
+    }
+}
+
+// undistorter for the frames of a stream, None if there is no calibration
+pub fn get_undistorter(lens: &Option<LensCalibration>) -> Option<Undistorter> {
+    match lens.as_ref()?.undistorter() {
+        Ok(undistorter) => Some(undistorter),
+        Err(e) => {
+            error!("Could not load lens calibration ({:})", e);
+            None
+        }
+    }
+}
+
+fn board_points(board: Size) -> Vector<Point3f> {
+    let mut points = Vector::new();
+    for row in 0..board.height {
+        for col in 0..board.width {
+            // unit squares, the scale of the board does not change the intrinsics
+            points.push(Point3f::new(col as f32, row as f32, 0.0));
+        }
+    }
+
+    return points;
+}
+
+fn mean_distance(a: &Vector<Point2f>, b: &Vector<Point2f>) -> f64 {
+    let n = a.len().min(b.len());
+    if n == 0 {
+        return f64::MAX;
+    }
+
+    let mut sum = 0.0;
+    for (p, q) in a.iter().zip(b.iter()) {
+        sum += ((p.x - q.x) as f64).hypot((p.y - q.y) as f64);
+    }
+
+    return sum / n as f64;
+}
+
+fn calibrate_lens(views: &Vector<Vector<Point2f>>, board: Size, image_size: Size) -> Result<LensCalibration, opencv::Error> {
+    let mut object_points = Vector::<Vector<Point3f>>::new();
+    for _ in 0..views.len() {
+        object_points.push(board_points(board));
+    }
+
+    let mut camera_matrix = Mat::default();
+    let mut dist_coeffs = Mat::default();
+    let mut rvecs = Vector::<Mat>::new();
+    let mut tvecs = Vector::<Mat>::new();
+    let criteria = TermCriteria::new(TermCriteria_Type::COUNT as i32 + TermCriteria_Type::EPS as i32, 30, f64::EPSILON)?;
+    let rms = calibrate_camera(&object_points, views, image_size, &mut camera_matrix, &mut dist_coeffs, &mut rvecs, &mut tvecs, 0, criteria)?;
+
+    let mut camera = [[0.0; 3]; 3];
+    let mut converted = Mat::default();
+    camera_matrix.convert_to(&mut converted, CV_64F, 1.0, 0.0)?;
+    for row in 0..3 {
+        for col in 0..3 {
+            camera[row][col] = *converted.at_2d::<f64>(row as i32, col as i32)?;
+        }
+    }
+
+    let mut coeffs = Mat::default();
+    dist_coeffs.convert_to(&mut coeffs, CV_64F, 1.0, 0.0)?;
+
+    Ok(LensCalibration {
+        image_size: [image_size.width, image_size.height],
+        camera_matrix: camera,
+        dist_coeffs: coeffs.data_typed::<f64>()?.to_vec(),
+        rms,
+    })
+}
+
+// collects views of a chessboard with `board` inner corners (columns x rows) held in front of the
+// camera at different positions and saves the resulting lens calibration for the camera
+pub fn grab_lens_frames(
+    source: Box<dyn FrameSource>,
+    camera_id: String,
+    board: Size,
+    window: Window,
+    rx: Receiver<()>,
+) {
+    struct FrameState {
+        camera_id: String,
+        board: Size,
+        start_time: Option<f64>,
+        last_attempt: f64,
+        views: Vector<Vector<Point2f>>,
+    }
+
+    #[derive(Serialize, Clone)]
+    struct ProgressPayload {
+        views: usize,
+        required_views: usize,
+        board_found: bool,
+    }
+
+    #[derive(Serialize, Clone)]
+    struct LensFinishedPayload {
+        success: bool,
+        rms: f64,
+        error_msg: String,
+    }
+
+    let frame_state = FrameState { camera_id, board, start_time: None, last_attempt: f64::MIN, views: Vector::new() };
+
+    let grab_frame = |frame: Mat, frame_time: FrameTime, frame_state: &mut FrameState, window: &Window| -> bool {
+        let curr_time = frame_time.pts;
+        let start_time = *frame_state.start_time.get_or_insert(curr_time);
+        if curr_time - start_time > LENS_TIMEOUT {
+            info!("Lens calibration failed: timeout");
+            window
+                .emit("lens_calibration_finished", LensFinishedPayload {
+                    success: false,
+                    rms: 0.0,
+                    error_msg: "Board was not captured from enough positions".to_string(),
+                })
+                .unwrap();
+            return false; // stop grabbing frames
+        }
+
+        if curr_time - frame_state.last_attempt < DETECT_INTERVAL {
+            return true; // continue onto next frame
+        }
+        frame_state.last_attempt = curr_time;
+
+        let mut corners = Vector::<Point2f>::new();
+        let flags = CALIB_CB_ADAPTIVE_THRESH + CALIB_CB_NORMALIZE_IMAGE + CALIB_CB_FAST_CHECK;
+        let found = find_chessboard_corners(&frame, frame_state.board, &mut corners, flags).unwrap_or(false);
+
+        let is_new_view = found && match frame_state.views.len() {
+            0 => true,
+            n => frame_state.views.get(n - 1).map(|last| mean_distance(&last, &corners) > MIN_VIEW_CHANGE).unwrap_or(true),
+        };
+        if is_new_view {
+            let criteria = TermCriteria::new(TermCriteria_Type::COUNT as i32 + TermCriteria_Type::EPS as i32, 30, 0.01).unwrap();
+            if let Err(e) = corner_sub_pix(&frame, &mut corners, Size::new(11, 11), Size::new(-1, -1), criteria) {
+                warn!("Could not refine board corners ({:})", e);
+            }
+            frame_state.views.push(corners);
+            info!("Captured lens calibration view {:}", frame_state.views.len());
+        }
+
+        window
+            .emit("lens_calibration_progress", ProgressPayload {
+                views: frame_state.views.len(),
+                required_views: REQUIRED_VIEWS,
+                board_found: found,
+            })
+            .unwrap();
+
+        if frame_state.views.len() < REQUIRED_VIEWS {
+            return true; // continue onto next frame
+        }
+
+        let image_size = Size::new(frame.cols(), frame.rows());
+        match calibrate_lens(&frame_state.views, frame_state.board, image_size) {
+            Ok(lens) => {
+                info!("Lens calibration success, rms {:}px", lens.rms);
+                let rms = lens.rms;
+                update_camera_config(window, &frame_state.camera_id, |config| config.lens = Some(lens));
+                window
+                    .emit("lens_calibration_finished", LensFinishedPayload { success: true, rms, error_msg: "".to_string() })
+                    .unwrap();
+            }
+            Err(e) => {
+                error!("Lens calibration failed ({:})", e);
+                window
+                    .emit("lens_calibration_finished", LensFinishedPayload {
+                        success: false,
+                        rms: 0.0,
+                        error_msg: "Could not calibrate lens".to_string(),
+                    })
+                    .unwrap();
+            }
+        }
+
+        return false; // stop grabbing frames
+    };
+
+    match camera_stream(source, rx, frame_state, grab_frame, window) {
+        Ok(()) => (),
+        Err(e) => {
+            error!("Could not read frames from camera ({:})", e.to_string());
+            ()
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use log::{error, warn};
+use opencv::core::{mean, no_array};
+use opencv::prelude::*;
+use serde::Serialize;
+
+use crate::detector::marker_levels;
+use crate::tracker::Tracked;
+
+// weight of the newest frame in the measured levels
+static LEVEL_SMOOTHING: f64 = 0.1;
+// share of the marker to background contrast kept clear of either level by the thresholds
+static THRESH_MARGIN: f64 = 0.25;
+// narrowest band the blob detector can work with, it needs a blob at two threshold steps
+static MIN_BAND: f64 = 11.0;
+// gray levels the thresholds have to move before the detector is rebuilt
+static MIN_THRESH_CHANGE: u32 = 4;
+// least gray levels between marker and background the marker can be told apart with
+static MIN_CONTRAST: f64 = 25.0;
+
+// measured lighting, sent with `lighting_drift` when it becomes unworkable or workable again
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Lighting {
+    pub workable: bool,
+    // mean gray levels of the marker and its background as the detector sees them, the marker
+    // is the darker one whatever its polarity
+    pub marker_level: f64,
+    pub background_level: f64,
+    pub min_thresh: u32,
+    pub max_thresh: u32,
+}
+
+// follows the marker and background intensity in the region searched for the aim and moves the
+// detector thresholds with them
+pub struct AdaptiveThresholds {
+    marker_level: Option<f64>,
+    background_level: f64,
+    // mean of the region when the levels were last measured on the marker
+    reference_mean: f64,
+    thresholds: (u32, u32),
+    workable: bool,
+}
+
+fn region_mean(gray: &Mat) -> Option<f64> {
+    match mean(gray, &no_array()) {
+        Ok(mean) => Some(mean[0]),
+        Err(e) => {
+            error!("Could not measure lighting ({:})", e);
+            None
+        }
+    }
+}
+
+impl AdaptiveThresholds {
+    pub fn new(min_thresh: u32, max_thresh: u32) -> AdaptiveThresholds {
+        AdaptiveThresholds {
+            marker_level: None,
+            background_level: 0.0,
+            reference_mean: 0.0,
+            thresholds: (min_thresh, max_thresh),
+            workable: true,
+        }
+    }
+
+    fn lighting(&self) -> Lighting {
+        Lighting {
+            workable: self.workable,
+            marker_level: self.marker_level.unwrap_or(0.0),
+            background_level: self.background_level,
+            min_thresh: self.thresholds.0,
+            max_thresh: self.thresholds.1,
+        }
+    }
+
+    // measures a prepared region, `marker` is the tracked marker in pixels of the region if it
+    // was detected in it. Returns the new thresholds if they moved and the lighting if it became
+    // workable or unworkable
+    pub fn update(&mut self, gray: &Mat, marker: Option<Tracked>) -> (Option<(u32, u32)>, Option<Lighting>) {
+        let region_mean = match region_mean(gray) {
+            Some(region_mean) => region_mean,
+            None => return (None, None),
+        };
+
+        let levels = marker
+            .filter(|marker| !marker.interpolated)
+            .and_then(|marker| marker_levels(gray, marker.x, marker.y, marker.size));
+        match (levels, self.marker_level) {
+            (Some((inside, around)), None) => {
+                self.marker_level = Some(inside);
+                self.background_level = around;
+                self.reference_mean = region_mean;
+            }
+            (Some((inside, around)), Some(marker_level)) => {
+                self.marker_level = Some(marker_level + LEVEL_SMOOTHING * (inside - marker_level));
+                self.background_level += LEVEL_SMOOTHING * (around - self.background_level);
+                self.reference_mean = region_mean;
+            }
+            (None, Some(marker_level)) => {
+                // without the marker the lighting change is taken from the whole region, which
+                // scales both levels alike
+                if self.reference_mean > 0.0 {
+                    let ratio = region_mean / self.reference_mean;
+                    self.marker_level = Some((marker_level * ratio).min(255.0));
+                    self.background_level = (self.background_level * ratio).min(255.0);
+                    self.reference_mean = region_mean;
+                }
+            }
+            (None, None) => return (None, None), // nothing to adapt to before the marker was seen
+        }
+
+        let marker_level = self.marker_level.unwrap();
+        let contrast = self.background_level - marker_level;
+        let workable = contrast >= MIN_CONTRAST;
+        let lighting_changed = workable != self.workable;
+        self.workable = workable;
+        if lighting_changed && !workable {
+            warn!("Lighting drifted out of a workable range, marker {:} and background {:}", marker_level, self.background_level);
+        }
+
+        let mut new_thresholds = None;
+        if workable {
+            let min_thresh = (marker_level + THRESH_MARGIN * contrast).max(0.0);
+            let max_thresh = (self.background_level - THRESH_MARGIN * contrast).max(min_thresh + MIN_BAND).min(255.0);
+            let thresholds = (min_thresh as u32, max_thresh as u32);
+            let moved = |a: u32, b: u32| (a as i64 - b as i64).abs() >= MIN_THRESH_CHANGE as i64;
+            if moved(thresholds.0, self.thresholds.0) || moved(thresholds.1, self.thresholds.1) {
+                self.thresholds = thresholds;
+                new_thresholds = Some(thresholds);
+            }
+        }
+
+        return (new_thresholds, if lighting_changed { Some(self.lighting()) } else { None });
+    }
+}
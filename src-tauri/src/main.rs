@@ -5,26 +5,59 @@
 
 extern crate ffmpeg_next as ffmpeg;
 
-use log::{info, LevelFilter};
+use log::{error, info, LevelFilter};
 use log4rs::Config;
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Root};
 use log4rs::encode::pattern::PatternEncoder;
-use tauri::{Window, State};
+use opencv::core::Size;
+use serde::Serialize;
+use tauri::{Manager, Window, State};
 use std::env;
+use std::path::PathBuf;
 use std::sync::Mutex;
-use std::sync::mpsc::{channel, Sender};
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender};
 use std::thread::spawn;
 use std::time::Instant;
 
+mod autotune;
+use autotune::ThresholdTuning;
 mod camera;
+use camera::CaptureProfile;
+mod config;
+use config::{load_camera_config, resolve_capture_profile, update_camera_config, CamerasLock};
+mod controls;
+use controls::CameraControls;
+mod debug;
+use debug::{debug_dir, write_debug_zip};
+mod detector;
+use detector::{DetectorKind, DetectorParams, Polarity};
+mod devices;
+mod diagnostics;
+use devices::{list_cameras, list_mics};
+mod lens;
+mod lighting;
+mod mapping;
+use mapping::{resolve_mapping, TargetMapping};
+use lens::{get_undistorter, grab_lens_frames};
 mod mic;
+mod orientation;
+use orientation::Orientation;
+mod playback;
+mod queue;
+mod recorder;
+use playback::PlaybackCommand;
 mod thread;
 use thread::Thread;
+mod tracker;
+mod smoothing;
+mod source;
+use source::{open_source, FrameFormat};
+mod synthetic;
 mod settings;
 use settings::{display_camera_feed, display_volume};
 mod shoot;
-use shoot::{grab_shoot_frames, mic_trigger};
+use shoot::{grab_shoot_frames, mic_trigger, TargetKind, RATIO1};
 mod calibrate;
 use calibrate::grab_calib_frames;
 
@@ -33,8 +66,11 @@ struct ManagedAppState(Mutex<AppState>);
 struct AppState {
     camera_thread: Option<Thread<()>>,
     threshs_tx: Option<Sender<(u32, u32)>>,
+    // each request carries the channel the tuning is replied on
+    auto_tune_tx: Option<Sender<SyncSender<Option<ThresholdTuning>>>>,
     mic_thread: Option<Thread<()>>,
-    trigger_tx: Option<Sender<Instant>>
+    trigger_tx: Option<Sender<Instant>>,
+    playback_tx: Option<Sender<PlaybackCommand>>
 }
 
 #[tauri::command]
@@ -71,21 +107,37 @@ fn start_calib_video(
     camera_label: String,
     min_thresh: u32,
     max_thresh: u32,
+    target: Option<TargetKind>,
+    profile: Option<CaptureProfile>,
     window: Window,
     state: State<ManagedAppState>,
 ) {
+    let profile = resolve_capture_profile(&window, &camera_label, profile);
+    let camera_config = load_camera_config(&window, &camera_label);
+
     // lock mutex to get value
     let mut curr_state = state.0.lock().unwrap();
 
     // create channels to terminate camera and mic threads and for mic triggers
     let (tx, rx) = channel();
     let (trigger_tx, trigger_rx) = channel();
+    let (playback_tx, playback_rx) = channel();
 
     // start thread to grab camera
+    let source = open_source(&camera_label, profile, FrameFormat::Gray, playback_rx, Some(trigger_tx.clone()), window.clone());
     let handle = spawn(move || grab_calib_frames(
+        source,
         camera_label,
         min_thresh,
         max_thresh,
+        camera_config.polarity,
+        camera_config.detector,
+        camera_config.detector_params,
+        camera_config.orientation,
+        camera_config.orientation_locked,
+        target.unwrap_or_default(),
+        get_undistorter(&camera_config.lens),
+        camera_config.mapping,
         trigger_rx,
         window,
         rx,
@@ -93,6 +145,7 @@ fn start_calib_video(
     let name = "grab_calib_frames".to_string();
     curr_state.camera_thread = Some(Thread{name, handle, tx});
     curr_state.trigger_tx = Some(trigger_tx);
+    curr_state.playback_tx = Some(playback_tx);
 
     // remove lock
     drop(curr_state);
@@ -102,27 +155,60 @@ fn start_calib_video(
 fn start_shoot_video(
     camera_label: String,
     calibrate_point: [f64; 2],
+    // mm per pixel measured by calibration
+    scale: Option<f64>,
     fine_adjust: [f64; 2],
     min_thresh: u32,
     max_thresh: u32,
+    profile: Option<CaptureProfile>,
+    record_path: Option<String>,
+    // trace smoothing from 0 (off) to 1
+    smoothing: Option<f64>,
+    // move the thresholds with lighting changes during the session
+    adaptive_thresholds: Option<bool>,
+    // write frames of failed detections to the debug folder
+    debug_frames: Option<bool>,
     window: Window,
     state: State<ManagedAppState>,
 ) {
+    let profile = resolve_capture_profile(&window, &camera_label, profile);
+    let record_path = record_path.map(PathBuf::from);
+    let camera_config = load_camera_config(&window, &camera_label);
+    let undistorter = get_undistorter(&camera_config.lens);
+    let debug_dir = if debug_frames.unwrap_or(false) { debug_dir(&window) } else { None };
+    let mapping = resolve_mapping(
+        camera_config.mapping,
+        calibrate_point,
+        scale.unwrap_or(RATIO1),
+        camera_config.orientation,
+        &undistorter,
+    );
+
     // lock mutex to get value
     let mut curr_state = state.0.lock().unwrap();
 
     // create channels to terminate camera and mic threads and for mic triggers
     let (tx, rx) = channel();
     let (trigger_tx, trigger_rx) = channel();
+    let (playback_tx, playback_rx) = channel();
 
     // start thread to grab camera
+    let source = open_source(&camera_label, profile, FrameFormat::Gray, playback_rx, Some(trigger_tx.clone()), window.clone());
     let handle = spawn(move || grab_shoot_frames(
-        camera_label,
-        calibrate_point,
+        source,
+        mapping,
         fine_adjust,
         min_thresh,
         max_thresh,
+        camera_config.polarity,
+        camera_config.detector,
+        camera_config.detector_params,
+        undistorter,
+        smoothing.unwrap_or(0.0),
+        adaptive_thresholds.unwrap_or(false),
+        debug_dir,
         true,
+        record_path,
         trigger_rx,
         window,
         rx,
@@ -130,11 +216,73 @@ fn start_shoot_video(
     let name = "grab_shoot_frames".to_string();
     curr_state.camera_thread = Some(Thread{name, handle, tx});
     curr_state.trigger_tx = Some(trigger_tx);
+    curr_state.playback_tx = Some(playback_tx);
+
+    // remove lock
+    drop(curr_state);
+}
+
+// lens calibration wizard, a chessboard with `board_cols` x `board_rows` inner corners is held
+// in front of the camera until enough views were captured
+#[tauri::command]
+fn start_lens_calibration(
+    camera_label: String,
+    board_cols: i32,
+    board_rows: i32,
+    profile: Option<CaptureProfile>,
+    window: Window,
+    state: State<ManagedAppState>,
+) {
+    let profile = resolve_capture_profile(&window, &camera_label, profile);
+
+    // lock mutex to get value
+    let mut curr_state = state.0.lock().unwrap();
+
+    // create channel to terminate camera thread
+    let (tx, rx) = channel();
+    let (playback_tx, playback_rx) = channel();
+
+    // start thread to grab camera
+    let source = open_source(&camera_label, profile, FrameFormat::Gray, playback_rx, None, window.clone());
+    let board = Size::new(board_cols, board_rows);
+    let handle = spawn(move || grab_lens_frames(source, camera_label, board, window, rx));
+    let name = "grab_lens_frames".to_string();
+    curr_state.camera_thread = Some(Thread{name, handle, tx});
+    curr_state.playback_tx = Some(playback_tx);
 
     // remove lock
     drop(curr_state);
 }
 
+#[tauri::command]
+fn clear_lens_calibration(camera_id: String, window: Window) {
+    update_camera_config(&window, &camera_id, |config| config.lens = None);
+}
+
+// perspective mapping from four frame pixels and the target positions in mm they show, used
+// instead of the mapping derived from calibration until cleared
+#[tauri::command]
+fn set_target_points(camera_id: String, pixels: [[f64; 2]; 4], target: [[f64; 2]; 4], window: Window) {
+    // the mapping is applied to undistorted marker positions, so the pixels are undistorted too
+    let mut pixels = pixels;
+    if let Some(undistorter) = get_undistorter(&load_camera_config(&window, &camera_id).lens) {
+        for pixel in pixels.iter_mut() {
+            let (x, y) = undistorter.undistort((pixel[0], pixel[1]));
+            *pixel = [x, y];
+        }
+    }
+
+    match TargetMapping::from_points(pixels, target) {
+        Ok(mapping) => update_camera_config(&window, &camera_id, |config| config.mapping = Some(mapping)),
+        Err(e) => error!("Could not compute target mapping ({:})", e),
+    }
+}
+
+#[tauri::command]
+fn clear_target_points(camera_id: String, window: Window) {
+    update_camera_config(&window, &camera_id, |config| config.mapping = None);
+}
+
 #[tauri::command]
 fn stop_webcam_and_mic(state: State<ManagedAppState>) {
     // lock mutex to get value
@@ -163,9 +311,14 @@ fn settings_choose_camera(
     height: u32,
     min_thresh: u32,
     max_thresh: u32,
+    profile: Option<CaptureProfile>,
     window: Window,
     state: State<ManagedAppState>,
 ) {
+    let profile = resolve_capture_profile(&window, &label, profile);
+    let camera_config = load_camera_config(&window, &label);
+    let (polarity, detector_kind, detector_params) = (camera_config.polarity, camera_config.detector, camera_config.detector_params);
+
     // lock mutex to get value
     let mut curr_state = state.0.lock().unwrap();
     
@@ -173,11 +326,18 @@ fn settings_choose_camera(
     let (tx_threshs, rx_threshs) = channel();
     curr_state.threshs_tx = Some(tx_threshs);
 
+    // create channel to request threshold tuning
+    let (tx_auto_tune, rx_auto_tune) = channel();
+    curr_state.auto_tune_tx = Some(tx_auto_tune);
+
     // start thread to grab camera
     let (tx, rx) = channel();
-    let handle = spawn(move || display_camera_feed(label, width, height, min_thresh, max_thresh, window, rx, rx_threshs));
+    let (playback_tx, playback_rx) = channel();
+    let source = open_source(&label, profile, FrameFormat::Rgb, playback_rx, None, window.clone());
+    let handle = spawn(move || display_camera_feed(source, width, height, min_thresh, max_thresh, polarity, detector_kind, detector_params, window, rx, rx_threshs, rx_auto_tune));
     let name = "display_camera_feed".to_string();
     curr_state.camera_thread = Some(Thread{name, handle, tx});
+    curr_state.playback_tx = Some(playback_tx);
 
     // remove lock
     drop(curr_state);
@@ -196,6 +356,7 @@ fn settings_close_camera(state: State<ManagedAppState>) {
         // close threshold changes channel
         drop(curr_state.threshs_tx.take().unwrap());
         curr_state.threshs_tx = None;
+        curr_state.auto_tune_tx = None;
     }
 
     // remove lock
@@ -217,6 +378,178 @@ fn settings_threshs_changed(min_thresh: u32, max_thresh: u32, state: State<Manag
     drop(curr_state);
 }
 
+// samples frames of the settings preview and sweeps the detection thresholds, the result is
+// applied to the preview and returned, None if no thresholds show a steady marker
+#[tauri::command]
+async fn auto_tune_thresholds(window: Window) -> Option<ThresholdTuning> {
+    // lock mutex to get value, the lock is released at the end of the block before awaiting
+    let auto_tune_tx = {
+        let state = window.state::<ManagedAppState>();
+        let curr_state = state.0.lock().unwrap();
+        curr_state.auto_tune_tx.clone()
+    };
+
+    let auto_tune_tx = match auto_tune_tx {
+        Some(auto_tune_tx) => auto_tune_tx,
+        None => {
+            error!("Cannot tune thresholds without the settings preview");
+            return None;
+        }
+    };
+    let (reply_tx, reply_rx) = sync_channel(1);
+    if let Err(error) = auto_tune_tx.send(reply_tx) {
+        error!("Could not request threshold tuning ({:})", error);
+        return None;
+    }
+
+    // the preview replies once it has sampled enough frames, or drops the channel when it closes
+    match tauri::async_runtime::spawn_blocking(move || reply_rx.recv()).await {
+        Ok(Ok(tuning)) => tuning,
+        Ok(Err(_)) => {
+            info!("Settings preview closed before thresholds were tuned");
+            None
+        }
+        Err(error) => {
+            error!("Could not wait for threshold tuning ({:})", error);
+            None
+        }
+    }
+}
+
+// packs the frames written for failed detections into a zip file at `path` for a bug report,
+// the result is sent with `debug_frames_zipped`
+#[tauri::command]
+fn zip_debug_frames(path: String, window: Window) {
+    #[derive(Serialize, Clone)]
+    struct ZippedPayload {
+        success: bool,
+        error_msg: String
+    }
+
+    let result = match debug_dir(&window) {
+        Some(dir) => write_debug_zip(&dir, &PathBuf::from(&path)).map_err(|e| e.to_string()),
+        None => Err("No app folder".to_string()),
+    };
+    let payload = match result {
+        Ok(()) => ZippedPayload { success: true, error_msg: "".to_string() },
+        Err(e) => {
+            error!("Could not zip debug frames to {:} ({:})", path, e);
+            ZippedPayload { success: false, error_msg: "Could not zip debug frames".to_string() }
+        }
+    };
+    window
+        .emit("debug_frames_zipped", payload)
+        .unwrap();
+}
+
+fn send_playback_command(command: PlaybackCommand, state: State<ManagedAppState>) {
+    // lock mutex to get value
+    let curr_state = state.0.lock().unwrap();
+
+    if let Some(playback_tx) = curr_state.playback_tx.as_ref() {
+        if let Err(error) = playback_tx.send(command) {
+            error!("Could not send playback command {:?} ({:?})", command, error);
+        }
+    }
+
+    // remove lock
+    drop(curr_state);
+}
+
+#[tauri::command]
+fn playback_pause(state: State<ManagedAppState>) {
+    send_playback_command(PlaybackCommand::Pause, state);
+}
+
+#[tauri::command]
+fn playback_resume(state: State<ManagedAppState>) {
+    send_playback_command(PlaybackCommand::Resume, state);
+}
+
+#[tauri::command]
+fn playback_seek(seconds: f64, state: State<ManagedAppState>) {
+    send_playback_command(PlaybackCommand::Seek(seconds), state);
+}
+
+#[tauri::command]
+fn playback_set_speed(speed: f64, state: State<ManagedAppState>) {
+    send_playback_command(PlaybackCommand::Speed(speed), state);
+}
+
+#[tauri::command]
+fn get_capture_profile(camera_id: String, window: Window) -> CaptureProfile {
+    resolve_capture_profile(&window, &camera_id, None)
+}
+
+#[tauri::command]
+fn set_capture_profile(camera_id: String, profile: CaptureProfile, window: Window) {
+    update_camera_config(&window, &camera_id, |config| config.profile = Some(profile));
+}
+
+#[tauri::command]
+fn get_camera_controls(camera_id: String, window: Window) -> CameraControls {
+    resolve_capture_profile(&window, &camera_id, None).controls
+}
+
+// saved with the capture profile, applies the next time the camera is started
+#[tauri::command]
+fn set_camera_controls(camera_id: String, controls: CameraControls, window: Window) {
+    let mut profile = resolve_capture_profile(&window, &camera_id, None);
+    profile.controls = controls;
+    update_camera_config(&window, &camera_id, |config| config.profile = Some(profile));
+}
+
+#[tauri::command]
+fn get_marker_polarity(camera_id: String, window: Window) -> Polarity {
+    load_camera_config(&window, &camera_id).polarity
+}
+
+// applies the next time the camera is started
+#[tauri::command]
+fn set_marker_polarity(camera_id: String, polarity: Polarity, window: Window) {
+    update_camera_config(&window, &camera_id, |config| config.polarity = polarity);
+}
+
+#[tauri::command]
+fn get_marker_detector(camera_id: String, window: Window) -> DetectorKind {
+    load_camera_config(&window, &camera_id).detector
+}
+
+// applies the next time the camera is started
+#[tauri::command]
+fn set_marker_detector(camera_id: String, detector: DetectorKind, window: Window) {
+    update_camera_config(&window, &camera_id, |config| config.detector = detector);
+}
+
+#[tauri::command]
+fn get_detector_params(camera_id: String, window: Window) -> DetectorParams {
+    load_camera_config(&window, &camera_id).detector_params
+}
+
+// marker area and shape limits, None restores the defaults, applies the next time the camera is
+// started
+#[tauri::command]
+fn set_detector_params(camera_id: String, params: Option<DetectorParams>, window: Window) {
+    update_camera_config(&window, &camera_id, |config| config.detector_params = params.unwrap_or_default());
+}
+
+#[tauri::command]
+fn get_camera_orientation(camera_id: String, window: Window) -> Orientation {
+    load_camera_config(&window, &camera_id).orientation
+}
+
+// a fixed orientation is kept through calibration, None lets calibration infer it again
+#[tauri::command]
+fn set_camera_orientation(camera_id: String, orientation: Option<Orientation>, window: Window) {
+    update_camera_config(&window, &camera_id, |config| {
+        config.orientation_locked = orientation.is_some();
+        config.pending_orientation = None;
+        if let Some(orientation) = orientation {
+            config.orientation = orientation;
+        }
+    });
+}
+
 #[tauri::command]
 fn settings_choose_mic(
     label: String,
@@ -273,7 +606,8 @@ fn main() {
 
     tauri::Builder::default()
         .manage(ManagedAppState(Default::default()))
-        .invoke_handler(tauri::generate_handler![settings_choose_camera, settings_close_camera, settings_choose_mic, settings_close_mic, settings_threshs_changed, start_shoot_video, start_audio, stop_webcam_and_mic, start_calib_video])
+        .manage(CamerasLock::default())
+        .invoke_handler(tauri::generate_handler![settings_choose_camera, settings_close_camera, settings_choose_mic, settings_close_mic, settings_threshs_changed, auto_tune_thresholds, zip_debug_frames, start_shoot_video, start_audio, stop_webcam_and_mic, start_calib_video, list_cameras, list_mics, get_capture_profile, set_capture_profile, get_camera_controls, set_camera_controls, get_marker_polarity, set_marker_polarity, get_marker_detector, set_marker_detector, get_detector_params, set_detector_params, get_camera_orientation, set_camera_orientation, start_lens_calibration, clear_lens_calibration, set_target_points, clear_target_points, playback_pause, playback_resume, playback_seek, playback_set_speed])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,221 @@
+use opencv::core::{Point2f, Rect, Vector, DECOMP_LU};
+use opencv::imgproc::get_perspective_transform;
+use opencv::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::lens::Undistorter;
+use crate::orientation::Orientation;
+
+type Matrix = [[f64; 3]; 3];
+
+fn apply(h: &Matrix, point: (f64, f64)) -> Option<(f64, f64)> {
+    let w = h[2][0] * point.0 + h[2][1] * point.1 + h[2][2];
+    if w.abs() < f64::EPSILON {
+        return None;
+    }
+
+    return Some((
+        (h[0][0] * point.0 + h[0][1] * point.1 + h[0][2]) / w,
+        (h[1][0] * point.0 + h[1][1] * point.1 + h[1][2]) / w,
+    ));
+}
+
+fn invert(h: &Matrix) -> Option<Matrix> {
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| h[r0][c0] * h[r1][c1] - h[r0][c1] * h[r1][c0];
+    let adjugate = [
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+    ];
+    let det = h[0][0] * adjugate[0][0] + h[0][1] * adjugate[1][0] + h[0][2] * adjugate[2][0];
+    if det.abs() < f64::EPSILON {
+        return None;
+    }
+
+    let mut inverse = [[0.0; 3]; 3];
+    for row in 0..3 {
+        for col in 0..3 {
+            inverse[row][col] = adjugate[row][col] / det;
+        }
+    }
+
+    return Some(inverse);
+}
+
+// homography from full frame pixels, undistorted if the lens is calibrated, to target mm
+// (x right, y up, target center at the origin)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct TargetMapping {
+    pub homography: Matrix,
+}
+
+impl TargetMapping {
+    // square-on mapping where the aim center is the target center, `scale` is in mm per pixel
+    pub fn from_calibration(aim_center: (f64, f64), scale: f64, orientation: Orientation) -> TargetMapping {
+        // columns of the orientation's linear map
+        let (a, c) = orientation.to_target(1.0, 0.0);
+        let (b, d) = orientation.to_target(0.0, 1.0);
+        let (cx, cy) = aim_center;
+
+        TargetMapping {
+            homography: [
+                [a * scale, b * scale, -(a * cx + b * cy) * scale],
+                [c * scale, d * scale, -(c * cx + d * cy) * scale],
+                [0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // perspective mapping from four pixel positions and the target positions in mm they show
+    pub fn from_points(pixels: [[f64; 2]; 4], target: [[f64; 2]; 4]) -> Result<TargetMapping, opencv::Error> {
+        let to_vector = |points: [[f64; 2]; 4]| -> Vector<Point2f> {
+            points.iter().map(|point| Point2f::new(point[0] as f32, point[1] as f32)).collect()
+        };
+        let matrix = get_perspective_transform(&to_vector(pixels), &to_vector(target), DECOMP_LU)?;
+
+        let mut homography = [[0.0; 3]; 3];
+        for row in 0..3 {
+            for col in 0..3 {
+                homography[row][col] = *matrix.at_2d::<f64>(row as i32, col as i32)?;
+            }
+        }
+
+        Ok(TargetMapping { homography })
+    }
+
+    pub fn to_target(&self, pixel: (f64, f64)) -> Option<(f64, f64)> {
+        apply(&self.homography, pixel)
+    }
+
+    pub fn to_pixels(&self, target: (f64, f64)) -> Option<(f64, f64)> {
+        apply(&invert(&self.homography)?, target)
+    }
+
+    // pixels of a `frame` showing the square of `size` mm around the target center, clipped to
+    // the frame, None if none of the square is in the frame
+    pub fn roi(&self, size: f64, frame: &Mat) -> Option<Rect> {
+        let half = size / 2.0;
+        let mut min = (f64::MAX, f64::MAX);
+        let mut max = (f64::MIN, f64::MIN);
+        for corner in [(-half, -half), (half, -half), (half, half), (-half, half)] {
+            let pixel = self.to_pixels(corner)?;
+            min = (min.0.min(pixel.0), min.1.min(pixel.1));
+            max = (max.0.max(pixel.0), max.1.max(pixel.1));
+        }
+
+        let x = min.0.max(0.0).floor() as i32;
+        let y = min.1.max(0.0).floor() as i32;
+        let width = max.0.min(frame.cols() as f64).ceil() as i32 - x;
+        let height = max.1.min(frame.rows() as f64).ceil() as i32 - y;
+        if width <= 0 || height <= 0 {
+            return None;
+        }
+
+        return Some(Rect::new(x, y, width, height));
+    }
+}
+
+// mapping for a session calibrated at `calibrate_point`, a mapping measured from four points takes
+// precedence
+pub fn resolve_mapping(
+    fixed: Option<TargetMapping>,
+    calibrate_point: [f64; 2],
+    scale: f64,
+    orientation: Orientation,
+    undistorter: &Option<Undistorter>,
+) -> TargetMapping {
+    if let Some(mapping) = fixed {
+        return mapping;
+    }
+
+    let mut aim_center = (calibrate_point[0], calibrate_point[1]);
+    if let Some(undistorter) = undistorter.as_ref() {
+        aim_center = undistorter.undistort(aim_center);
+    }
+
+    return TargetMapping::from_calibration(aim_center, scale, orientation);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{Scalar, CV_8UC1};
+
+    fn assert_near(actual: (f64, f64), expected: (f64, f64), tolerance: f64) {
+        assert!(
+            (actual.0 - expected.0).abs() <= tolerance && (actual.1 - expected.1).abs() <= tolerance,
+            "{:?} is not {:?}", actual, expected
+        );
+    }
+
+    fn frame() -> Mat {
+        Mat::new_rows_cols_with_default(720, 1280, CV_8UC1, Scalar::all(0.0)).unwrap()
+    }
+
+    // camera looking at the target at an angle, the corners of a 100mm square
+    fn tilted() -> TargetMapping {
+        let pixels = [[500.0, 300.0], [780.0, 310.0], [800.0, 420.0], [480.0, 410.0]];
+        let target = [[-50.0, 50.0], [50.0, 50.0], [50.0, -50.0], [-50.0, -50.0]];
+        TargetMapping::from_points(pixels, target).unwrap()
+    }
+
+    #[test]
+    fn calibration_maps_the_aim_center_to_the_target_center() {
+        let mapping = TargetMapping::from_calibration((640.0, 360.0), 0.5, Orientation::default());
+        assert_near(mapping.to_target((640.0, 360.0)).unwrap(), (0.0, 0.0), 1e-9);
+        // image right is target up, image up is target right
+        assert_near(mapping.to_target((660.0, 360.0)).unwrap(), (0.0, 10.0), 1e-9);
+        assert_near(mapping.to_target((640.0, 340.0)).unwrap(), (10.0, 0.0), 1e-9);
+    }
+
+    #[test]
+    fn target_round_trips_through_pixels() {
+        let mut mappings = vec![tilted()];
+        for rotation in [0, 90, 180, 270] {
+            for mirror_horizontal in [false, true] {
+                let orientation = Orientation { rotation, mirror_horizontal, ..Orientation::default() };
+                mappings.push(TargetMapping::from_calibration((612.0, 371.0), 0.67, orientation));
+            }
+        }
+
+        for mapping in mappings.iter() {
+            for target in [(0.0, 0.0), (12.5, -3.0), (-40.0, 40.0), (85.0, 0.0)] {
+                let pixels = mapping.to_pixels(target).unwrap();
+                assert_near(mapping.to_target(pixels).unwrap(), target, 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn points_map_onto_their_target_positions() {
+        let mapping = tilted();
+        assert_near(mapping.to_target((500.0, 300.0)).unwrap(), (-50.0, 50.0), 1e-3);
+        assert_near(mapping.to_target((780.0, 310.0)).unwrap(), (50.0, 50.0), 1e-3);
+        assert_near(mapping.to_target((800.0, 420.0)).unwrap(), (50.0, -50.0), 1e-3);
+        assert_near(mapping.to_target((480.0, 410.0)).unwrap(), (-50.0, -50.0), 1e-3);
+        assert_near(mapping.to_pixels((50.0, -50.0)).unwrap(), (800.0, 420.0), 1e-3);
+    }
+
+    #[test]
+    fn roi_covers_the_target_square() {
+        // 100mm at 0.5mm per pixel is 200 pixels around the aim center
+        let mapping = TargetMapping::from_calibration((640.0, 360.0), 0.5, Orientation::default());
+        assert_eq!(mapping.roi(100.0, &frame()), Some(Rect::new(540, 260, 200, 200)));
+
+        // the corners of the square bound the roi of a tilted camera
+        let roi = tilted().roi(100.0, &frame()).unwrap();
+        let bounds = [roi.x, roi.y, roi.x + roi.width, roi.y + roi.height];
+        for (bound, expected) in bounds.iter().zip([480, 300, 800, 420]) {
+            assert!((bound - expected).abs() <= 1, "roi {:?}", roi);
+        }
+    }
+
+    #[test]
+    fn roi_is_clipped_to_the_frame() {
+        let mapping = TargetMapping::from_calibration((20.0, 700.0), 0.5, Orientation::default());
+        assert_eq!(mapping.roi(100.0, &frame()), Some(Rect::new(0, 600, 120, 120)));
+
+        let outside = TargetMapping::from_calibration((-500.0, 360.0), 0.5, Orientation::default());
+        assert_eq!(outside.roi(100.0, &frame()), None);
+    }
+}
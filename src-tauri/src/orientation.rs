@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+// how the camera is mounted on the barrel relative to the default mount, where image right is
+// target up and image up is target right
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Orientation {
+    // clockwise rotation of the image in degrees, one of 0, 90, 180 or 270
+    pub rotation: u32,
+    // image is flipped left to right, applied before the rotation
+    pub mirror_horizontal: bool,
+    // image is flipped top to bottom, applied before the rotation
+    pub mirror_vertical: bool,
+}
+
+impl Orientation {
+    fn quarter_turns(&self) -> u32 {
+        ((self.rotation + 45) / 90) % 4
+    }
+
+    // turn a pixel offset from the aim center (x right, y down) into target axes (x right, y up),
+    // still in pixels
+    pub fn to_target(&self, dx: f64, dy: f64) -> (f64, f64) {
+        let mut dx = if self.mirror_horizontal { -dx } else { dx };
+        let mut dy = if self.mirror_vertical { -dy } else { dy };
+
+        // undo the rotation of the image a quarter turn at a time
+        for _ in 0..self.quarter_turns() {
+            let prev_dx = dx;
+            dx = dy;
+            dy = -prev_dx;
+        }
+
+        return (-dy, dx);
+    }
+
+    // orientation with the configured mirroring under which the pixel offset of the raise above
+    // the target points up, None if the raise is too small to tell
+    pub fn infer(&self, raise: (f64, f64), min_raise: f64) -> Option<Orientation> {
+        if raise.0.hypot(raise.1) < min_raise {
+            return None;
+        }
+
+        let mut best: Option<(Orientation, f64)> = None;
+        for quarter_turns in 0..4 {
+            let orientation = Orientation { rotation: quarter_turns * 90, ..*self };
+            let (_, up) = orientation.to_target(raise.0, raise.1);
+            if best.map(|(_, best_up)| up > best_up).unwrap_or(true) {
+                best = Some((orientation, up));
+            }
+        }
+
+        return best.map(|(orientation, _)| orientation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every mount with where image right and image up end up in target axes
+    static MOUNTS: [(u32, bool, bool, (f64, f64), (f64, f64)); 16] = [
+        (0, false, false, (0.0, 1.0), (1.0, 0.0)),
+        (90, false, false, (1.0, 0.0), (0.0, -1.0)),
+        (180, false, false, (0.0, -1.0), (-1.0, 0.0)),
+        (270, false, false, (-1.0, 0.0), (0.0, 1.0)),
+        (0, false, true, (0.0, 1.0), (-1.0, 0.0)),
+        (90, false, true, (1.0, 0.0), (0.0, 1.0)),
+        (180, false, true, (0.0, -1.0), (1.0, 0.0)),
+        (270, false, true, (-1.0, 0.0), (0.0, -1.0)),
+        (0, true, false, (0.0, -1.0), (1.0, 0.0)),
+        (90, true, false, (-1.0, 0.0), (0.0, -1.0)),
+        (180, true, false, (0.0, 1.0), (-1.0, 0.0)),
+        (270, true, false, (1.0, 0.0), (0.0, 1.0)),
+        (0, true, true, (0.0, -1.0), (-1.0, 0.0)),
+        (90, true, true, (-1.0, 0.0), (0.0, 1.0)),
+        (180, true, true, (0.0, 1.0), (1.0, 0.0)),
+        (270, true, true, (1.0, 0.0), (0.0, -1.0)),
+    ];
+
+    fn mounts() -> impl Iterator<Item = (Orientation, (f64, f64), (f64, f64))> {
+        MOUNTS.iter().map(|(rotation, mirror_horizontal, mirror_vertical, right, up)| {
+            (Orientation { rotation: *rotation, mirror_horizontal: *mirror_horizontal, mirror_vertical: *mirror_vertical }, *right, *up)
+        })
+    }
+
+    #[test]
+    fn image_axes_map_onto_target_axes() {
+        for (orientation, right, up) in mounts() {
+            assert_eq!(orientation.to_target(1.0, 0.0), right, "image right under {:?}", orientation);
+            assert_eq!(orientation.to_target(0.0, -1.0), up, "image up under {:?}", orientation);
+            // offsets scale and flip with the pixel offset
+            assert_eq!(orientation.to_target(-3.0, 0.0), (-3.0 * right.0, -3.0 * right.1), "image left under {:?}", orientation);
+        }
+    }
+
+    #[test]
+    fn mirroring_both_ways_is_half_a_turn() {
+        for rotation in [0, 90, 180, 270] {
+            let mirrored = Orientation { rotation, mirror_horizontal: true, mirror_vertical: true };
+            let turned = Orientation { rotation: (rotation + 180) % 360, ..Orientation::default() };
+            for offset in [(1.0, 0.0), (0.0, 1.0), (2.0, -5.0)] {
+                assert_eq!(mirrored.to_target(offset.0, offset.1), turned.to_target(offset.0, offset.1));
+            }
+        }
+    }
+
+    #[test]
+    fn raise_above_the_target_infers_the_rotation() {
+        for (orientation, right, up) in mounts() {
+            // the pixel direction that is target up under this mount, from its image axes
+            let raise = if right.1 > 0.0 {
+                (80.0, 0.0)
+            } else if right.1 < 0.0 {
+                (-80.0, 0.0)
+            } else if up.1 > 0.0 {
+                (0.0, -80.0)
+            } else {
+                (0.0, 80.0)
+            };
+            // slightly off straight up, as an approach rarely is
+            let raise = (raise.0 + 0.2 * raise.1, raise.1 + 0.2 * raise.0);
+
+            let unrotated = Orientation { rotation: 0, ..orientation };
+            assert_eq!(unrotated.infer(raise, 50.0), Some(orientation), "raise {:?}", raise);
+        }
+    }
+
+    #[test]
+    fn small_raise_infers_nothing() {
+        assert_eq!(Orientation::default().infer((30.0, 30.0), 50.0), None);
+    }
+}
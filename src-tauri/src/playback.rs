@@ -0,0 +1,122 @@
+use log::info;
+
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+pub static MIN_SPEED: f64 = 0.25;
+pub static MAX_SPEED: f64 = 4.0;
+
+// frames later than this are not caught up on, the clock is re-anchored instead
+static MAX_LAG: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+pub enum PlaybackCommand {
+    Pause,
+    Resume,
+    Seek(f64), // seconds from start of file
+    Speed(f64),
+}
+
+// result of waiting for the next frame to be due
+pub enum PlaybackEvent {
+    Continue,
+    Seek(f64),
+    Terminate,
+}
+
+// paces frames of a recorded file by their presentation timestamps
+pub struct Playback {
+    rx: Receiver<PlaybackCommand>,
+    speed: f64,
+    paused: bool,
+    // wall clock instant at which the frame with the anchor pts was shown
+    anchor: Option<(Instant, f64)>,
+    last_pts: f64,
+}
+
+impl Playback {
+    pub fn new(rx: Receiver<PlaybackCommand>) -> Playback {
+        Playback { rx, speed: 1.0, paused: false, anchor: None, last_pts: 0.0 }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.anchor = None;
+    }
+
+    fn handle(&mut self, command: PlaybackCommand) -> Option<PlaybackEvent> {
+        info!("Playback command {:?}", command);
+        match command {
+            PlaybackCommand::Pause => self.pause(),
+            PlaybackCommand::Resume => {
+                self.paused = false;
+                self.anchor = None;
+            }
+            PlaybackCommand::Seek(seconds) => {
+                self.anchor = None;
+                return Some(PlaybackEvent::Seek(seconds.max(0.0)));
+            }
+            PlaybackCommand::Speed(speed) => {
+                self.speed = speed.max(MIN_SPEED).min(MAX_SPEED);
+                // keep the current position, only the rate from here on changes
+                self.anchor = Some((Instant::now(), self.last_pts));
+            }
+        }
+
+        None
+    }
+
+    // apply pending commands, blocking while paused; `terminate_rx` is polled so a paused
+    // playback can still be stopped
+    pub fn poll(&mut self, terminate_rx: &Receiver<()>) -> PlaybackEvent {
+        loop {
+            loop {
+                match self.rx.try_recv() {
+                    Ok(command) => {
+                        if let Some(event) = self.handle(command) {
+                            return event;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            match terminate_rx.try_recv() {
+                Ok(_) | Err(TryRecvError::Disconnected) => return PlaybackEvent::Terminate,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            if !self.paused {
+                return PlaybackEvent::Continue;
+            }
+            sleep(Duration::from_millis(20));
+        }
+    }
+
+    // sleep until the frame with the given pts (in seconds) is due
+    pub fn wait_for(&mut self, pts: f64) {
+        let (anchor_time, anchor_pts) = match self.anchor {
+            Some(anchor) => anchor,
+            None => {
+                self.anchor = Some((Instant::now(), pts));
+                self.last_pts = pts;
+                return;
+            }
+        };
+
+        let due = (pts - anchor_pts) / self.speed;
+        let elapsed = anchor_time.elapsed().as_secs_f64();
+        if due > elapsed {
+            sleep(Duration::from_secs_f64(due - elapsed));
+        } else if elapsed - due > MAX_LAG || due < 0.0 {
+            // processing fell behind or pts jumped backwards
+            self.anchor = Some((Instant::now(), pts));
+        }
+        self.last_pts = pts;
+    }
+}
@@ -0,0 +1,252 @@
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::source::{Frame, FrameTime};
+
+// frames older than this when processing starts count as late
+static LATE_AFTER: Duration = Duration::from_millis(100);
+
+// what waiting for a frame gave
+pub enum Popped {
+    Frame(Frame),
+    // nothing arrived in time
+    Empty,
+    // closed and drained
+    Closed,
+}
+
+struct QueueState {
+    frames: VecDeque<Frame>,
+    closed: bool,
+    captured: u64,
+    dropped: u64,
+}
+
+// bounded hand-over of frames from the capture thread to processing, dropping the oldest
+// frame when processing falls behind so it always works on the freshest one
+pub struct FrameQueue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+    capacity: usize,
+}
+
+impl FrameQueue {
+    pub fn new(capacity: usize) -> FrameQueue {
+        FrameQueue {
+            state: Mutex::new(QueueState { frames: VecDeque::with_capacity(capacity), closed: false, captured: 0, dropped: 0 }),
+            available: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    // returns false once the queue was closed and capture should stop
+    pub fn push(&self, frame: Frame) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+
+        state.captured += 1;
+        if state.frames.len() >= self.capacity {
+            state.frames.pop_front();
+            state.dropped += 1;
+        }
+        state.frames.push_back(frame);
+        self.available.notify_one();
+
+        return true;
+    }
+
+    // blocks until a frame is available, for at most `timeout`, and takes the newest one,
+    // frames queued before it are dropped
+    pub fn pop_timeout(&self, timeout: Duration) -> Popped {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(frame) = state.frames.pop_back() {
+                state.dropped += state.frames.len() as u64;
+                state.frames.clear();
+                return Popped::Frame(frame);
+            }
+            if state.closed {
+                return Popped::Closed;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Popped::Empty;
+            }
+            state = self.available.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+
+    // frames captured and dropped so far
+    fn counts(&self) -> (u64, u64) {
+        let state = self.state.lock().unwrap();
+        return (state.captured, state.dropped);
+    }
+}
+
+// payload of the frame_stats event, counts are totals since the stream started
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct FrameStats {
+    pub captured: u64,
+    pub processed: u64,
+    pub dropped: u64,
+    pub late: u64,
+    // mean time from capture to the start of processing over the last interval
+    pub latency_ms: f64,
+}
+
+// accumulates the processing side of FrameStats between reports
+pub struct StatsCounter {
+    stats: FrameStats,
+    // captured and dropped by queues of replaced capture threads
+    retired: (u64, u64),
+    latency_sum: f64,
+    latency_count: u64,
+    last_report: Instant,
+}
+
+impl StatsCounter {
+    pub fn new() -> StatsCounter {
+        StatsCounter { stats: FrameStats::default(), retired: (0, 0), latency_sum: 0.0, latency_count: 0, last_report: Instant::now() }
+    }
+
+    // keeps the counts of a queue that is replaced so the totals carry on
+    pub fn retire(&mut self, queue: &FrameQueue) {
+        let (captured, dropped) = queue.counts();
+        self.retired = (self.retired.0 + captured, self.retired.1 + dropped);
+    }
+
+    pub fn processing(&mut self, time: &FrameTime) {
+        let latency = Instant::now().saturating_duration_since(time.instant);
+        if latency > LATE_AFTER {
+            self.stats.late += 1;
+        }
+        self.stats.processed += 1;
+        self.latency_sum += latency.as_secs_f64();
+        self.latency_count += 1;
+    }
+
+    // stats to report if `interval` passed since the last report
+    pub fn report(&mut self, queue: &FrameQueue, interval: Duration) -> Option<FrameStats> {
+        if self.last_report.elapsed() < interval {
+            return None;
+        }
+        self.last_report = Instant::now();
+
+        let (captured, dropped) = queue.counts();
+        self.stats.captured = self.retired.0 + captured;
+        self.stats.dropped = self.retired.1 + dropped;
+        self.stats.latency_ms = if self.latency_count > 0 {
+            1000.0 * self.latency_sum / self.latency_count as f64
+        } else {
+            0.0
+        };
+        self.latency_sum = 0.0;
+        self.latency_count = 0;
+
+        return Some(self.stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::Mat;
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
+
+    // frames are told apart by their pts
+    fn frame(pts: f64) -> Frame {
+        Frame { mat: Mat::default(), time: FrameTime { pts, instant: Instant::now() } }
+    }
+
+    fn popped_pts(popped: Popped) -> Option<f64> {
+        match popped {
+            Popped::Frame(frame) => Some(frame.time.pts),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest_frame() {
+        let queue = FrameQueue::new(2);
+        for pts in [0.0, 1.0, 2.0, 3.0] {
+            assert!(queue.push(frame(pts)));
+        }
+        assert_eq!(queue.counts(), (4, 2));
+
+        // the newest frame is processed and the one queued before it dropped
+        assert_eq!(popped_pts(queue.pop_timeout(Duration::ZERO)), Some(3.0));
+        assert_eq!(queue.counts(), (4, 3));
+        assert!(matches!(queue.pop_timeout(Duration::ZERO), Popped::Empty));
+    }
+
+    #[test]
+    fn frames_kept_up_with_are_not_dropped() {
+        let queue = FrameQueue::new(2);
+        for pts in [0.0, 1.0, 2.0] {
+            queue.push(frame(pts));
+            assert_eq!(popped_pts(queue.pop_timeout(Duration::ZERO)), Some(pts));
+        }
+        assert_eq!(queue.counts(), (3, 0));
+    }
+
+    #[test]
+    fn closed_queue_is_drained_before_it_reports_closed() {
+        let queue = FrameQueue::new(2);
+        queue.push(frame(0.0));
+        queue.close();
+
+        assert!(!queue.push(frame(1.0)));
+        assert_eq!(popped_pts(queue.pop_timeout(Duration::ZERO)), Some(0.0));
+        assert!(matches!(queue.pop_timeout(Duration::from_secs(1)), Popped::Closed));
+        assert_eq!(queue.counts(), (1, 0));
+    }
+
+    #[test]
+    fn waiting_pop_wakes_up_for_a_pushed_frame() {
+        let queue = Arc::new(FrameQueue::new(2));
+        let capture_queue = queue.clone();
+        let capture = spawn(move || {
+            sleep(Duration::from_millis(20));
+            capture_queue.push(frame(5.0));
+        });
+
+        assert_eq!(popped_pts(queue.pop_timeout(Duration::from_secs(5))), Some(5.0));
+        capture.join().unwrap();
+    }
+
+    #[test]
+    fn stats_carry_the_counts_of_retired_queues() {
+        let mut stats = StatsCounter::new();
+        let retired = FrameQueue::new(1);
+        retired.push(frame(0.0));
+        retired.push(frame(1.0));
+        stats.retire(&retired);
+
+        let queue = FrameQueue::new(1);
+        queue.push(frame(2.0));
+        let processed = match queue.pop_timeout(Duration::ZERO) {
+            Popped::Frame(frame) => frame,
+            _ => panic!("no frame"),
+        };
+        stats.processing(&processed.time);
+        // a frame captured long before processing starts is late
+        stats.processing(&FrameTime { pts: 3.0, instant: Instant::now() - 2 * LATE_AFTER });
+
+        let report = stats.report(&queue, Duration::ZERO).unwrap();
+        assert_eq!((report.captured, report.dropped, report.processed, report.late), (3, 1, 2, 1));
+        assert!(report.latency_ms >= LATE_AFTER.as_secs_f64() * 1000.0);
+        assert!(stats.report(&queue, Duration::from_secs(60)).is_none());
+    }
+}
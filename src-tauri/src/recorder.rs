@@ -0,0 +1,211 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use ffmpeg::ffi::{AVCodecID, AVMediaType};
+use ffmpeg::format::context::Output;
+use ffmpeg::format::stream::Stream;
+use ffmpeg::{codec, encoder, Error, Packet, Rational};
+use log::{error, info, warn};
+
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Instant;
+
+// how long a trigger subtitle stays on screen, in seconds
+static TRIGGER_DURATION: f64 = 0.5;
+
+// events of the shooting session stored alongside the recorded video
+#[derive(Clone, Copy, Debug)]
+pub enum RecordMarker {
+    Trigger(Instant),
+    ClearTrace(Instant),
+    ShotFinished(Instant),
+}
+
+// where to record to and the markers to store with the recording
+pub struct Recording {
+    pub path: PathBuf,
+    pub markers: Receiver<RecordMarker>,
+}
+
+pub fn mark(marker_tx: &Option<Sender<RecordMarker>>, marker: RecordMarker) {
+    if let Some(marker_tx) = marker_tx {
+        // recording might have failed to start in which case markers are dropped
+        let _ = marker_tx.send(marker);
+    }
+}
+
+// muxes camera packets into a file without re-encoding
+pub struct Recorder {
+    output: Output,
+    markers: Receiver<RecordMarker>,
+    in_time_base: Rational,
+    video_time_base: Rational,
+    subtitle_time_base: Rational,
+    is_mp4: bool,
+    // wall clock instant of the first recorded packet
+    start: Option<Instant>,
+    // subtracted from input timestamps so the recording starts at zero and stays continuous
+    offset: Option<i64>,
+    last_dts: Option<i64>,
+    last_write: Instant,
+    shot_start: Option<f64>,
+    n_shots: i64,
+}
+
+impl Recorder {
+    pub fn new(recording: Recording, in_stream: &Stream) -> Result<Recorder, Error> {
+        let is_mp4 = recording
+            .path
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("mp4") || ext.eq_ignore_ascii_case("mov"))
+            .unwrap_or(false);
+        let mut output = ffmpeg::format::output(&recording.path)?;
+
+        let mut video = output.add_stream(encoder::find(codec::Id::None))?;
+        video.set_parameters(in_stream.parameters());
+        unsafe {
+            // let the muxer choose a tag valid for its container
+            (*video.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+
+        let mut subtitle = output.add_stream(encoder::find(codec::Id::None))?;
+        let mut params = codec::Parameters::new();
+        unsafe {
+            (*params.as_mut_ptr()).codec_type = AVMediaType::AVMEDIA_TYPE_SUBTITLE;
+            (*params.as_mut_ptr()).codec_id = if is_mp4 {
+                AVCodecID::AV_CODEC_ID_MOV_TEXT
+            } else {
+                AVCodecID::AV_CODEC_ID_SUBRIP
+            };
+        }
+        subtitle.set_parameters(params);
+        subtitle.set_time_base(Rational::new(1, 1000));
+
+        output.write_header()?;
+        info!("Recording to {:}", recording.path.display());
+
+        // muxer may have changed the time bases when writing the header
+        let video_time_base = output.stream(0).unwrap().time_base();
+        let subtitle_time_base = output.stream(1).unwrap().time_base();
+
+        Ok(Recorder {
+            output,
+            markers: recording.markers,
+            in_time_base: in_stream.time_base(),
+            video_time_base,
+            subtitle_time_base,
+            is_mp4,
+            start: None,
+            offset: None,
+            last_dts: None,
+            last_write: Instant::now(),
+            shot_start: None,
+            n_shots: 0,
+        })
+    }
+
+    // seconds since the start of the recording
+    fn seconds_since_start(&self, instant: Instant) -> f64 {
+        match self.start {
+            Some(start) => instant.saturating_duration_since(start).as_secs_f64(),
+            None => 0.0,
+        }
+    }
+
+    // input timestamps restart after a reconnect, continue the recording after the gap
+    pub fn discontinuity(&mut self) {
+        self.offset = None;
+    }
+
+    pub fn write(&mut self, packet: &Packet) {
+        let now = Instant::now();
+        self.start.get_or_insert(now);
+        let packet_ts = packet.dts().or(packet.pts()).unwrap_or(0);
+        let offset = match self.offset {
+            Some(offset) => offset,
+            None => {
+                let resume_at = match self.last_dts {
+                    Some(last_dts) => {
+                        let gap = now.duration_since(self.last_write).as_secs_f64() / f64::from(self.in_time_base);
+                        last_dts + (gap as i64).max(1)
+                    }
+                    None => 0,
+                };
+                *self.offset.insert(packet_ts - resume_at)
+            }
+        };
+        self.last_write = now;
+
+        let mut packet = packet.clone();
+        packet.set_pts(packet.pts().map(|pts| pts - offset));
+        packet.set_dts(packet.dts().map(|dts| dts - offset));
+        if let (Some(dts), Some(last_dts)) = (packet.dts(), self.last_dts) {
+            if dts <= last_dts {
+                // seeking in a played back file jumps backwards, keep the recording monotonic
+                return;
+            }
+        }
+        self.last_dts = packet.dts();
+
+        packet.rescale_ts(self.in_time_base, self.video_time_base);
+        packet.set_stream(0);
+        packet.set_position(-1);
+        if let Err(e) = packet.write_interleaved(&mut self.output) {
+            warn!("Could not write packet to recording ({:})", e);
+        }
+
+        self.write_markers();
+    }
+
+    fn write_markers(&mut self) {
+        while let Ok(marker) = self.markers.try_recv() {
+            match marker {
+                RecordMarker::Trigger(instant) => {
+                    let time = self.seconds_since_start(instant);
+                    self.write_subtitle("Trigger", time);
+                }
+                RecordMarker::ClearTrace(instant) => {
+                    self.shot_start = Some(self.seconds_since_start(instant));
+                }
+                RecordMarker::ShotFinished(instant) => {
+                    let end = self.seconds_since_start(instant);
+                    let start = self.shot_start.take().unwrap_or(end);
+                    self.n_shots += 1;
+                    let title = format!("Shot {:}", self.n_shots);
+                    let millis = Rational::new(1, 1000);
+                    if let Err(e) = self.output.add_chapter(self.n_shots, millis, (start * 1000.0) as i64, (end * 1000.0) as i64, &title) {
+                        warn!("Could not add chapter {:} to recording ({:})", title, e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_subtitle(&mut self, text: &str, time: f64) {
+        let mut data = Vec::new();
+        if self.is_mp4 {
+            // mov_text samples are prefixed with their length
+            data.extend_from_slice(&(text.len() as u16).to_be_bytes());
+        }
+        data.extend_from_slice(text.as_bytes());
+
+        let time_base = f64::from(self.subtitle_time_base);
+        let pts = (time / time_base) as i64;
+        let mut packet = Packet::copy(&data);
+        packet.set_pts(Some(pts));
+        packet.set_dts(Some(pts));
+        packet.set_duration((TRIGGER_DURATION / time_base) as i64);
+        packet.set_stream(1);
+        if let Err(e) = packet.write_interleaved(&mut self.output) {
+            warn!("Could not write {:} to recording ({:})", text, e);
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.write_markers();
+        match self.output.write_trailer() {
+            Ok(()) => info!("Finished recording"),
+            Err(e) => error!("Could not finish recording ({:})", e),
+        }
+    }
+}
@@ -2,18 +2,18 @@ extern crate ffmpeg_next as ffmpeg;
 
 use base64::encode;
 use log::{info, error};
-use opencv::core::{Point, VecN, Size, Ptr, Vector};
-use opencv::features2d::{SimpleBlobDetector, SimpleBlobDetector_Params};
+use opencv::core::{Point, VecN, Size};
 use opencv::imgproc::{cvt_color, circle, LINE_8, FILLED, resize, INTER_LINEAR};
-use opencv::imgcodecs::imwrite;
 use opencv::prelude::*;
 use tauri::Window;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{sync_channel, Receiver, Sender, SyncSender};
 use std::time::Instant;
 
-use crate::camera::camera_stream;
+use crate::autotune::{tune_thresholds, ThresholdTuning, TUNE_FRAMES};
+use crate::detector::{Detector, DetectorKind, DetectorParams, Polarity};
+use crate::diagnostics::{BrightnessSource, DiagnosticsCollector, BRIGHTNESS_CAPACITY};
+use crate::source::{camera_stream, FrameSource, FrameTime};
 use crate::mic::mic_stream;
-use crate::shoot::detect_circles;
 
 pub fn display_volume(
     label: String,
@@ -37,48 +37,56 @@ pub fn display_volume(
 }
 
 pub fn display_camera_feed(
-    label: String,
+    source: Box<dyn FrameSource>,
     width: u32,
     height: u32,
     min_thresh: u32,
     max_thresh: u32,
+    polarity: Polarity,
+    detector_kind: DetectorKind,
+    detector_params: DetectorParams,
     window: Window,
     rx: Receiver<()>,
     rx_threshs: Receiver<(u32, u32)>,
+    rx_auto_tune: Receiver<SyncSender<Option<ThresholdTuning>>>,
 ) {
     struct FrameState {
         frame_index: u32,
         width: u32,
         height: u32,
         rx_threshs: Receiver<(u32, u32)>,
+        rx_auto_tune: Receiver<SyncSender<Option<ThresholdTuning>>>,
         start_time: Instant,
         prev_frame_time: Instant,
-        params: SimpleBlobDetector_Params,
-        detector: Ptr<SimpleBlobDetector>
+        polarity: Polarity,
+        detector_params: DetectorParams,
+        detector: Detector,
+        diagnostics: DiagnosticsCollector,
+        // frames sampled for threshold tuning while it is running and where to reply with the result
+        tune_frames: Option<(Vec<Mat>, SyncSender<Option<ThresholdTuning>>)>
     }
 
     let frame_index = 0;
     let start_time = Instant::now();
     let prev_frame_time = Instant::now();
-    let mut params = SimpleBlobDetector_Params::default().unwrap();
-    params.min_threshold = min_thresh as f32;
-    params.max_threshold = max_thresh as f32;
-
-    params.filter_by_color = false;
-    params.filter_by_convexity = false;
-
-    params.filter_by_area = true;
-    params.min_area = 450.0;
-    params.max_area = 10000.0;
-
-    params.filter_by_circularity = true;
-    params.min_circularity = 0.7;
-
-    params.filter_by_inertia = true;
-    params.min_inertia_ratio = 0.85;
-    let detector = SimpleBlobDetector::create(params).unwrap();
-    let frame_state = FrameState{ frame_index, width, height, rx_threshs, start_time, prev_frame_time, params, detector };
-    let grab_frame = |frame: Mat, frame_state: &mut FrameState, window: &Window| -> bool {
+    let detector = Detector::new(detector_kind, detector_params, polarity, min_thresh, max_thresh);
+    let (brightness_tx, brightness_rx) = sync_channel(BRIGHTNESS_CAPACITY);
+    let source = Box::new(BrightnessSource::new(source, brightness_tx));
+    let frame_state = FrameState{
+        frame_index,
+        width,
+        height,
+        rx_threshs,
+        rx_auto_tune,
+        start_time,
+        prev_frame_time,
+        polarity,
+        detector_params,
+        detector,
+        diagnostics: DiagnosticsCollector::new(brightness_rx),
+        tune_frames: None
+    };
+    let grab_frame = |frame: Mat, frame_time: FrameTime, frame_state: &mut FrameState, window: &Window| -> bool {
         if frame_state.prev_frame_time.elapsed().as_secs_f64() < 0.03 {
             // only process at 30fps for output to UI
             return true; // continue onto next frame
@@ -90,9 +98,7 @@ pub fn display_camera_feed(
         loop {
             match frame_state.rx_threshs.try_recv() {
                 Ok((min_thresh, max_thresh)) => {
-                    frame_state.params.min_threshold = min_thresh as f32;
-                    frame_state.params.max_threshold = max_thresh as f32;
-                    frame_state.detector = SimpleBlobDetector::create(frame_state.params).unwrap();
+                    frame_state.detector.set_thresholds(min_thresh, max_thresh);
                 },
                 Err(_) => {
                     break;
@@ -100,19 +106,70 @@ pub fn display_camera_feed(
             }
         }
 
+        // sample frames for threshold tuning if it was requested, a request made while tuning
+        // is dropped and so replied to with nothing
+        if let Ok(reply_tx) = frame_state.rx_auto_tune.try_recv() {
+            if frame_state.tune_frames.is_none() {
+                info!("Tuning detection thresholds");
+                frame_state.tune_frames = Some((Vec::new(), reply_tx));
+            }
+        }
+        if let Some((tune_frames, _)) = frame_state.tune_frames.as_mut() {
+            tune_frames.push(frame.clone());
+            if tune_frames.len() >= TUNE_FRAMES {
+                let (tune_frames, reply_tx) = frame_state.tune_frames.take().unwrap();
+                let tuning = tune_thresholds(&tune_frames, frame_state.polarity, frame_state.detector_params);
+                match tuning {
+                    Some(tuning) => {
+                        info!("Tuned detection thresholds to {:}-{:}, score {:}", tuning.min_thresh, tuning.max_thresh, tuning.score);
+                        frame_state.detector.set_thresholds(tuning.min_thresh, tuning.max_thresh);
+                    }
+                    None => info!("Threshold tuning failed: no stable marker"),
+                }
+                if reply_tx.send(tuning).is_err() {
+                    info!("Threshold tuning was no longer waited for");
+                }
+            }
+        }
+
         // image processing pipeline
-        // 1. clone frame to mutable
-        let mut input = frame.clone();
+        // 1. copy frame to mutable RGB, monochrome cameras are shown as gray
+        let mut input = Mat::default();
+        if frame.channels() == 1 {
+            if let Err(error) = cvt_color(&frame, &mut input, opencv::imgproc::COLOR_GRAY2RGB, 0) {
+                error!("Could not convert frame for display ({:})", error);
+                return true; // continue onto next frame
+            }
+        } else {
+            input = frame.clone();
+        }
 
         // 2. detect circles
-        let keypoints = detect_circles(&input, &mut frame_state.detector);
+        let prepared = match frame_state.detector.prepare(&frame) {
+            Ok(prepared) => prepared,
+            Err(error) => {
+                error!("Could not prepare frame for detection ({:})", error);
+                return true; // continue onto next frame
+            }
+        };
+        let detections = frame_state.detector.detect_prepared(&prepared);
+
+        // report image quality, focus and contrast are measured on a single marker
+        let marker = if detections.len() == 1 { Some(detections[0]) } else { None };
+        if let Some(diagnostics) = frame_state.diagnostics.report(&frame, &prepared, marker, frame_time.pts) {
+            window
+                .emit("camera_diagnostics", diagnostics)
+                .unwrap();
+        }
 
         // 3. draw detected circles 
         let color = VecN([255.0, 0.0, 0.0, 0.0]);
-        for keypoint in keypoints {
-            let center = Point{x: keypoint.pt.x as i32, y: keypoint.pt.y as i32};
-            let radius = (keypoint.size / 2.0) as i32;
-            circle(&mut input, center, radius, color, FILLED, LINE_8, 0);
+        for detection in detections {
+            let center = Point{x: detection.x as i32, y: detection.y as i32};
+            let radius = (detection.size / 2.0) as i32;
+            if let Err(error) = circle(&mut input, center, radius, color, FILLED, LINE_8, 0) {
+                error!("Could not draw detected circle ({:})", error);
+            }
         } 
         // let center_x = input.cols() / 2;
         // let center_y = input.rows() / 2;
@@ -120,11 +177,17 @@ pub fn display_camera_feed(
 
         // 4. resize frame to output
         let mut resized = Mat::default();
-        resize(&input, &mut resized, Size{width: frame_state.width as i32, height: frame_state.height as i32}, 0.0, 0.0, INTER_LINEAR);
+        if let Err(error) = resize(&input, &mut resized, Size{width: frame_state.width as i32, height: frame_state.height as i32}, 0.0, 0.0, INTER_LINEAR) {
+            error!("Could not resize frame for display ({:})", error);
+            return true; // continue onto next frame
+        }
 
         // 5. convert RGB to RGBA for displaying to canvas
         let mut output = Mat::default();
-        cvt_color(&resized, &mut output, opencv::imgproc::COLOR_RGB2RGBA, 0);
+        if let Err(error) = cvt_color(&resized, &mut output, opencv::imgproc::COLOR_RGB2RGBA, 0) {
+            error!("Could not convert frame for display ({:})", error);
+            return true; // continue onto next frame
+        }
         let data = match output.data_bytes() {
             Ok(res) => res,
             Err(error) => {
@@ -142,7 +205,7 @@ pub fn display_camera_feed(
         return true; // continue onto next frame
     };
 
-    match camera_stream(label, rx, frame_state, grab_frame, window) {
+    match camera_stream(source, rx, frame_state, grab_frame, window) {
         Ok(()) => (),
         Err(e) => {
             error!("Could not read frames from camera ({:})", e.to_string());
@@ -1,62 +1,77 @@
 extern crate ffmpeg_next as ffmpeg;
 
 use log::{error, info};
-use opencv::core::{Size, BORDER_DEFAULT, Vector, KeyPoint, no_array, Ptr, Rect};
-use opencv::features2d::{SimpleBlobDetector, SimpleBlobDetector_Params};
-use opencv::imgproc::{cvt_color, gaussian_blur};
+use opencv::core::Rect;
 use opencv::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tauri::Window;
-use std::sync::mpsc::{Receiver, Sender};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::collections::VecDeque;
 use std::time::{Instant, Duration};
-use cubic_splines::{Spline, BoundaryCondition};
 
-use crate::camera::camera_stream;
+use crate::debug::{DebugCapture, DebugReason};
+use crate::detector::{Detection, Detector, DetectorKind, DetectorParams, Polarity};
+use crate::source::{camera_stream, EventSink, FrameSource, FrameTime};
+use crate::recorder::{mark, RecordMarker, Recording};
+use crate::lens::{check_undistorter, Undistorter};
+use crate::lighting::AdaptiveThresholds;
+use crate::mapping::TargetMapping;
 use crate::mic::mic_stream;
+use crate::smoothing::Smoother;
+use crate::tracker::{Tracked, Tracker};
 
 // sizes in mm
 static TARGET_SIZE: f64 = 170.0;
-static RATIO1: f64 = 170.0 / 254.0;
+// mm per pixel used when calibration did not measure the scale
+pub static RATIO1: f64 = 170.0 / 254.0;
+// how far back frames are kept to find the one captured when the trigger was pulled
+static TRIGGER_HISTORY: Duration = Duration::from_secs(1);
+
+// target shot at, calibration measures the scale from the size of its aiming black
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetKind {
+    AirPistol,
+    AirRifle,
+}
+
+impl Default for TargetKind {
+    fn default() -> TargetKind {
+        TargetKind::AirPistol
+    }
+}
+
+impl TargetKind {
+    // diameter of the aiming black in mm
+    pub fn aiming_black_size(&self) -> f64 {
+        match self {
+            TargetKind::AirPistol => 59.5,
+            TargetKind::AirRifle => 30.5,
+        }
+    }
+}
 
 #[derive(Serialize, Clone, Copy)]
 pub struct TracePoint {
     pub x: f64,
     pub y: f64,
     pub time: f64, // time since shot start
+    pub confidence: f64, // of the detection, 0 to 1
+    pub interpolated: bool, // predicted while the marker was not detected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<[f64; 2]>, // position before smoothing, None when the trace is not smoothed
 }
 
-pub fn detect_circles(frame: &Mat, detector: &mut Ptr<SimpleBlobDetector>) -> Vector<KeyPoint> {
-    // if frame is not grayscale, convert it
-    let mut gray_frame = Mat::default();
-    if frame.channels() == 3 {
-        cvt_color(&frame, &mut gray_frame, opencv::imgproc::COLOR_RGB2GRAY, 0);
-    }
-  
-    let mut blurred_frame = Mat::default();
-    gaussian_blur(&gray_frame, &mut blurred_frame, Size{width: 9, height: 9}, 0.0, 0.0, BORDER_DEFAULT);
-
-    let mut keypoints = Vector::new();
-    detector.detect(&blurred_frame, &mut keypoints, &no_array());
-
-    return keypoints;
-}
-
-pub fn crop_frame(frame: &Mat, calibrate_point: [f64; 2]) -> Mat {
+// region around the aim center that is searched for the aim and where it is in the frame, the
+// whole frame if the target is not in view
+pub fn crop_frame(frame: &Mat, mapping: &TargetMapping) -> (Mat, Rect) {
     // clip 1.75x size of card around aim center
-    let mut width = ((1.75 * TARGET_SIZE) / RATIO1).floor();
-    let mut height = width;
-    let mut x = calibrate_point[0] - width / 2.0;
-    let mut y = calibrate_point[1] - height / 2.0;
-    
-    // clip x, y, width, height to be within the bounds of the frame
-    x = x.max(0.0);
-    y = y.max(0.0);
-    let frame_width = frame.cols() as f64;
-    let frame_height = frame.rows() as f64;
-    width = if x + width > frame_width { frame_width - x } else { width };
-    height = if y + height > frame_height { frame_height - y } else { height };
-
-    return Mat::roi(frame, Rect::new(x as i32, y as i32, width as i32, height as i32)).unwrap().clone();
+    let crop = mapping
+        .roi(1.75 * TARGET_SIZE, frame)
+        .unwrap_or_else(|| Rect::new(0, 0, frame.cols(), frame.rows()));
+
+    return (Mat::roi(frame, crop).unwrap().clone(), crop);
 }
 
 pub fn mic_trigger(
@@ -89,332 +104,402 @@ pub fn mic_trigger(
     }
 }
 
-pub fn get_circle_detector(min_thresh: u32, max_thresh: u32) -> Ptr<SimpleBlobDetector> {
-    let mut params = SimpleBlobDetector_Params::default().unwrap();
-    params.min_threshold = min_thresh as f32;
-    params.max_threshold = max_thresh as f32;
-
-    params.filter_by_color = false;
-    params.filter_by_convexity = false;
-
-    params.filter_by_area = true;
-    params.min_area = 450.0;
-    params.max_area = 10000.0;
-
-    params.filter_by_circularity = true;
-    params.min_circularity = 0.7;
-
-    params.filter_by_inertia = true;
-    params.min_inertia_ratio = 0.85;
-    return SimpleBlobDetector::create(params).unwrap();
-}
-
-pub fn grab_shoot_frames(
-    label: String,
-    calibrate_point: [f64; 2],
+// state carried from one frame of a shooting session to the next
+struct FrameState {
+    frame_index: u32,
+    shot_start_time: f64,
+    shot_started: bool,
+    circle_detected_time: f64,
+    before_trace: Vec<TracePoint>,
+    shot_point: Option<TracePoint>,
+    after_trace: Vec<TracePoint>,
+    pre_trace: Vec<TracePoint>,
+    mapping: TargetMapping,
     fine_adjust: [f64; 2],
-    min_thresh: u32,
-    max_thresh: u32,
     up_down: bool,
+    trigger_time: Option<Instant>,
+    // trace points of the last TRIGGER_HISTORY of the shot and when their frames were captured
+    recent: VecDeque<(Instant, TracePoint)>,
+    undistorter: Option<Undistorter>,
+    detector: Detector,
+    tracker: Tracker,
+    smoother: Smoother,
+    lighting: Option<AdaptiveThresholds>,
+    debug: Option<DebugCapture>,
     trigger_rx: Receiver<Instant>,
-    window: Window,
-    rx: Receiver<()>,
-) {
-    // define and initialize frame state
-    struct FrameState {
-        frame_index: u32,
-        shot_start_time: Instant,
-        shot_started: bool,
-        circle_detected_time: Instant,
-        before_trace: Vec<TracePoint>,
-        shot_point: Option<TracePoint>,
-        after_trace: Vec<TracePoint>,
-        pre_trace: Vec<TracePoint>,
-        calibrate_point: [f64; 2],
+    marker_tx: Option<Sender<RecordMarker>>
+}
+
+impl FrameState {
+    fn new(
+        mapping: TargetMapping,
         fine_adjust: [f64; 2],
         up_down: bool,
-        trigger_time: Option<Instant>,
-        detector: Ptr<SimpleBlobDetector>,
-        trigger_rx: Receiver<Instant>
+        undistorter: Option<Undistorter>,
+        detector: Detector,
+        smoother: Smoother,
+        lighting: Option<AdaptiveThresholds>,
+        debug: Option<DebugCapture>,
+        trigger_rx: Receiver<Instant>,
+        marker_tx: Option<Sender<RecordMarker>>,
+    ) -> FrameState {
+        FrameState {
+            frame_index: 0,
+            shot_start_time: 0.0,
+            shot_started: false,
+            circle_detected_time: 0.0,
+            before_trace: Vec::new(),
+            shot_point: None,
+            after_trace: Vec::new(),
+            pre_trace: Vec::new(),
+            mapping,
+            fine_adjust,
+            up_down,
+            trigger_time: None,
+            recent: VecDeque::new(),
+            undistorter,
+            detector,
+            tracker: Tracker::new(),
+            smoother,
+            lighting,
+            debug,
+            trigger_rx,
+            marker_tx
+        }
     }
+}
 
-    let frame_index = 0;
-    let detector = get_circle_detector(min_thresh, max_thresh);
-    let now = Instant::now();
-    let frame_state = FrameState { 
-        frame_index,
-        shot_start_time: now,
-        shot_started: false,
-        circle_detected_time: now,
-        before_trace: Vec::new(),
-        shot_point: None,
-        after_trace: Vec::new(),
-        pre_trace: Vec::new(),
-        calibrate_point,
-        fine_adjust,
-        up_down,
-        trigger_time: None,
-        detector,
-        trigger_rx
-    };
+// trace point of the frame captured closest to the trigger, None until a frame captured after
+// the trigger was seen
+fn closest_frame(recent: &VecDeque<(Instant, TracePoint)>, trigger_time: Instant) -> Option<TracePoint> {
+    let (newest, _) = recent.back()?;
+    if *newest < trigger_time {
+        return None;
+    }
 
-    let grab_frame = |frame: Mat, frame_state: &mut FrameState, window: &Window| -> bool {
-        let curr_time = Instant::now();
-        let time_since_shot_start = match frame_state.frame_index {
-            0 => 0.0,
-            _ => curr_time.duration_since(frame_state.shot_start_time).as_secs_f64()
-        };
+    let distance = |instant: &Instant| instant.saturating_duration_since(trigger_time).max(trigger_time.saturating_duration_since(*instant));
+    return recent.iter().min_by_key(|(instant, _)| distance(instant)).map(|(_, point)| *point);
+}
 
-        match frame_state.trigger_rx.try_recv() {
-            Ok(trigger_time) => {
-                frame_state.trigger_time = Some(trigger_time);
-            }
-            Err(_) => {}
+// follows the aim through one frame, starts, resets and finishes shots and sends the trace as it
+// grows
+fn grab_shoot_frame<E: EventSink>(frame: Mat, frame_time: FrameTime, frame_state: &mut FrameState, window: &E) -> bool {
+    // all shot timing is in capture time, the wall clock is only used to match triggers
+    let curr_time = frame_time.pts;
+    if frame_state.frame_index == 0 {
+        frame_state.shot_start_time = curr_time;
+        frame_state.circle_detected_time = curr_time;
+        check_undistorter(&mut frame_state.undistorter, &frame);
+    }
+    let time_since_shot_start = curr_time - frame_state.shot_start_time;
+
+    match frame_state.trigger_rx.try_recv() {
+        Ok(trigger_time) => {
+            frame_state.trigger_time = Some(trigger_time);
+            mark(&frame_state.marker_tx, RecordMarker::Trigger(trigger_time));
         }
+        Err(_) => {}
+    }
 
-        if frame_state.shot_started {
-            // shot has started i.e. the aim has went past the top edge and came back down
-            let time_since_circle_detected = curr_time.duration_since(frame_state.circle_detected_time).as_secs_f64();
-            if time_since_circle_detected > 2.0 {
-                // reset shot if shot has started but aim is not within the target/cannot be found
-                // for 2s
-                frame_state.shot_started = false;
+    let mut shot_reset = false;
+    if frame_state.shot_started {
+        // shot has started i.e. the aim has went past the top edge and came back down
+        let time_since_circle_detected = curr_time - frame_state.circle_detected_time;
+        if time_since_circle_detected > 2.0 {
+            // reset shot if shot has started but aim is not within the target/cannot be found
+            // for 2s
+            frame_state.shot_started = false;
+            frame_state.before_trace = Vec::new();
+            frame_state.shot_point = None;
+            frame_state.after_trace = Vec::new();
+            frame_state.recent.clear();
+            shot_reset = true;
+
+            window
+                .emit("clear_trace", {})
+                .unwrap();
+            mark(&frame_state.marker_tx, RecordMarker::ClearTrace(frame_time.instant));
+            // delay_read = 1000 / idle_fps;
+        } else {
+            if time_since_shot_start > 60.0 && frame_state.shot_point.is_none() {
+                // reset trace if shot has started but trigger has not been pulled for 60s
                 frame_state.before_trace = Vec::new();
                 frame_state.shot_point = None;
                 frame_state.after_trace = Vec::new();
+                frame_state.recent.clear();
 
                 window
                     .emit("clear_trace", {})
                     .unwrap();
-                // delay_read = 1000 / idle_fps;
-            } else {
-                if time_since_shot_start > 60.0 && frame_state.shot_point.is_none() {
-                    // reset trace if shot has started but trigger has not been pulled for 60s
-                    frame_state.before_trace = Vec::new();
-                    frame_state.shot_point = None;
-                    frame_state.after_trace = Vec::new();
-
-                    window
-                        .emit("clear_trace", {})
-                        .unwrap();
+                mark(&frame_state.marker_tx, RecordMarker::ClearTrace(frame_time.instant));
 
-                    // but update the start time to the current time
-                    frame_state.shot_start_time = curr_time;
-                } else if
-                    frame_state.shot_point.is_some() &&
-                    time_since_shot_start - frame_state.shot_point.unwrap().time >= 1.0
-                {
-                    // 1s after trigger is pulled, shot is finished. create new object for this shot
-                    // and draw the x-t and y-t graph
-                    let mut before_trace: Vec<TracePoint> = Vec::new();
-                    for trace_point in &frame_state.before_trace {
-                        before_trace.push(TracePoint { x: trace_point.x, y: trace_point.y, time: trace_point.time });
-                    }
-
-                    let mut after_trace: Vec<TracePoint> = Vec::new();
-                    for trace_point in &frame_state.after_trace {
-                        after_trace.push(TracePoint { x: trace_point.x, y: trace_point.y, time: trace_point.time });
-                    }
-
-                    #[derive(Serialize, Clone)]
-                    struct Payload {
-                        before_trace: Vec<TracePoint>,
-                        shot_point: TracePoint,
-                        after_trace: Vec<TracePoint>
-                    }
-                    window
-                        .emit("shot_finished", Payload {
-                            before_trace,
-                            shot_point: frame_state.shot_point.unwrap(),
-                            after_trace
-                        })
-                        .unwrap();
+                // but update the start time to the current time
+                frame_state.shot_start_time = curr_time;
+            } else if
+                frame_state.shot_point.is_some() &&
+                time_since_shot_start - frame_state.shot_point.unwrap().time >= 1.0
+            {
+                // 1s after trigger is pulled, shot is finished. create new object for this shot
+                // and draw the x-t and y-t graph
+                let mut before_trace: Vec<TracePoint> = Vec::new();
+                for trace_point in &frame_state.before_trace {
+                    before_trace.push(TracePoint { x: trace_point.x, y: trace_point.y, time: trace_point.time, confidence: trace_point.confidence, interpolated: trace_point.interpolated, raw: trace_point.raw });
+                }
 
-                    // reset shot
-                    frame_state.shot_started = false;
-                    frame_state.before_trace = Vec::new();
-                    frame_state.shot_point = None;
-                    frame_state.after_trace = Vec::new();
+                let mut after_trace: Vec<TracePoint> = Vec::new();
+                for trace_point in &frame_state.after_trace {
+                    after_trace.push(TracePoint { x: trace_point.x, y: trace_point.y, time: trace_point.time, confidence: trace_point.confidence, interpolated: trace_point.interpolated, raw: trace_point.raw });
+                }
 
-                    // delay_read = 1000 / idle_fps;
+                #[derive(Serialize, Clone)]
+                struct Payload {
+                    before_trace: Vec<TracePoint>,
+                    shot_point: TracePoint,
+                    after_trace: Vec<TracePoint>
                 }
+                window
+                    .emit("shot_finished", Payload {
+                        before_trace,
+                        shot_point: frame_state.shot_point.unwrap(),
+                        after_trace
+                    })
+                    .unwrap();
+                mark(&frame_state.marker_tx, RecordMarker::ShotFinished(frame_time.instant));
+
+                // reset shot
+                frame_state.shot_started = false;
+                frame_state.before_trace = Vec::new();
+                frame_state.shot_point = None;
+                frame_state.after_trace = Vec::new();
+                frame_state.recent.clear();
+
+                // delay_read = 1000 / idle_fps;
             }
         }
+    }
 
-        let cropped_frame = crop_frame(&frame, frame_state.calibrate_point);
-        let keypoints = detect_circles(&cropped_frame, &mut frame_state.detector);
-        let detected_circle = keypoints.len() == 1;
-
-        if detected_circle {
-            let circle = keypoints.get(0).unwrap();
-            // ramp up back to 120fps
-            // delay_read = 0;
-
-            // aim i.e. black circle was found
-            // flip & rotate the x, y to fit camera
-            let x = (-circle.pt.y as f64 + cropped_frame.rows() as f64 / 2.0) * RATIO1 + frame_state.fine_adjust[0];
-            let y = (circle.pt.x as f64 - cropped_frame.cols() as f64 / 2.0) * RATIO1 + frame_state.fine_adjust[1];
-            let center = TracePoint{
-                x,
-                y,
-                time: curr_time.duration_since(frame_state.shot_start_time).as_secs_f64(),
-            };
-
-            if x >= -TARGET_SIZE / 2.0 &&
-               x <= TARGET_SIZE / 2.0 &&
-               y >= -TARGET_SIZE / 2.0 &&
-               y <= TARGET_SIZE / 2.0
-            {
-                // aim is found and within the target
-                frame_state.circle_detected_time = curr_time;
+    let (cropped_frame, crop) = crop_frame(&frame, &frame_state.mapping);
+    let gray = match frame_state.detector.prepare(&cropped_frame) {
+        Ok(gray) => Some(gray),
+        Err(e) => {
+            error!("Could not prepare frame for detection ({:})", e);
+            None
+        }
+    };
+    let detections: Vec<Detection> = match gray.as_ref() {
+        Some(gray) => frame_state.detector
+            .detect_prepared(gray)
+            .into_iter()
+            .map(|detection| Detection { x: detection.x + crop.x as f64, y: detection.y + crop.y as f64, ..detection })
+            .collect(),
+        None => Vec::new(),
+    };
+    let tracked = frame_state.tracker.update(&detections, curr_time);
+
+    // keep frames of failed detections to debug with
+    if let Some(debug) = frame_state.debug.as_mut() {
+        let reason = match detections.len() {
+            _ if shot_reset => Some(DebugReason::ShotReset),
+            // the marker is only missed while the pistol is aimed at the target
+            0 if frame_state.shot_started => Some(DebugReason::NoMarker),
+            0 => None,
+            1 => None,
+            _ => Some(DebugReason::MultipleMarkers),
+        };
+        if let Some(reason) = reason {
+            debug.capture(&frame, crop, &detections, frame_state.detector.thresholds(), reason);
+        }
+    }
+
+    // move the thresholds with the lighting of the cropped region
+    if let (Some(lighting), Some(gray)) = (frame_state.lighting.as_mut(), gray.as_ref()) {
+        let marker = tracked.map(|circle| Tracked { x: circle.x - crop.x as f64, y: circle.y - crop.y as f64, ..circle });
+        let (thresholds, changed) = lighting.update(gray, marker);
+        if let Some((min_thresh, max_thresh)) = thresholds {
+            frame_state.detector.set_thresholds(min_thresh, max_thresh);
+        }
+        if let Some(changed) = changed {
+            window
+                .emit("lighting_drift", changed)
+                .unwrap();
+        }
+    }
+
+    // position of the tracked circle in the full frame without lens distortion, mapped to the
+    // target which flips & rotates the x, y to fit camera
+    let target_point = match tracked {
+        Some(circle) => {
+            let mut aim = (circle.x, circle.y);
+            if let Some(undistorter) = frame_state.undistorter.as_ref() {
+                aim = undistorter.undistort(aim);
             }
+            frame_state.mapping.to_target(aim).map(|(x, y)| (x, y, circle.confidence, circle.interpolated))
+        }
+        None => None,
+    };
+
+    if let Some((x, y, confidence, interpolated)) = target_point {
+        // ramp up back to 120fps
+        // delay_read = 0;
+
+        // aim i.e. black circle was found
+        let raw_x = x + frame_state.fine_adjust[0];
+        let raw_y = y + frame_state.fine_adjust[1];
+        let (x, y) = frame_state.smoother.update((raw_x, raw_y), curr_time, interpolated);
+        let center = TracePoint{
+            x,
+            y,
+            time: curr_time - frame_state.shot_start_time,
+            confidence,
+            interpolated,
+            raw: if frame_state.smoother.enabled() { Some([raw_x, raw_y]) } else { None },
+        };
 
-            if !frame_state.shot_started {
-                if frame_state.up_down {
-                    // if up/down detection is enabled, detect aim going up and down
-                    if frame_state.pre_trace.len() <= 1 {
-                        frame_state.pre_trace.push(center);
-                    } else {
-                        frame_state.pre_trace[0] = frame_state.pre_trace[1];
-                        frame_state.pre_trace[1] = center;
-                    }
-
-                    if frame_state.pre_trace.len() == 2 {
-                        // shot is started if the aim went past the edge (preTrace[0].y > TARGET_SIZE / 2)
-                        // and came back down after that (preTrace[1].y < TARGET_SIZE / 2)
-                        frame_state.shot_started =
-                            frame_state.pre_trace[0].y > TARGET_SIZE / 2.0 &&
-                            frame_state.pre_trace[1].y < TARGET_SIZE / 2.0;
-                    }
+        if x >= -TARGET_SIZE / 2.0 &&
+           x <= TARGET_SIZE / 2.0 &&
+           y >= -TARGET_SIZE / 2.0 &&
+           y <= TARGET_SIZE / 2.0
+        {
+            // aim is found and within the target
+            frame_state.circle_detected_time = curr_time;
+        }
+
+        if !frame_state.shot_started {
+            if frame_state.up_down {
+                // if up/down detection is enabled, detect aim going up and down
+                if frame_state.pre_trace.len() <= 1 {
+                    frame_state.pre_trace.push(center);
                 } else {
-                    // else shot is started from the frame the circle is detected
-                    frame_state.shot_started = true;
+                    frame_state.pre_trace[0] = frame_state.pre_trace[1];
+                    frame_state.pre_trace[1] = center;
                 }
 
-                if frame_state.shot_started {
-                    // new shot started
-                    // reset traces
-                    frame_state.before_trace = Vec::new();
-                    frame_state.shot_point = None;
-                    frame_state.after_trace = Vec::new();
-                    frame_state.pre_trace = Vec::new();
+                if frame_state.pre_trace.len() == 2 {
+                    // shot is started if the aim went past the edge (preTrace[0].y > TARGET_SIZE / 2)
+                    // and came back down after that (preTrace[1].y < TARGET_SIZE / 2)
+                    frame_state.shot_started =
+                        frame_state.pre_trace[0].y > TARGET_SIZE / 2.0 &&
+                        frame_state.pre_trace[1].y < TARGET_SIZE / 2.0;
+                }
+            } else {
+                // else shot is started from the frame the circle is detected
+                frame_state.shot_started = true;
+            }
+
+            if frame_state.shot_started {
+                // new shot started
+                // reset traces
+                frame_state.before_trace = Vec::new();
+                frame_state.shot_point = None;
+                frame_state.after_trace = Vec::new();
+                frame_state.recent.clear();
+                frame_state.pre_trace = Vec::new();
 
+                window
+                    .emit("clear_trace", {})
+                    .unwrap();
+                mark(&frame_state.marker_tx, RecordMarker::ClearTrace(frame_time.instant));
+
+                frame_state.shot_start_time = curr_time;
+            } 
+        } else if frame_state.shot_point.is_none() {
+            frame_state.before_trace.push(center);
+            frame_state.recent.push_back((frame_time.instant, center));
+            while frame_state.recent.front().map(|(instant, _)| frame_time.instant.saturating_duration_since(*instant) > TRIGGER_HISTORY).unwrap_or(false) {
+                frame_state.recent.pop_front();
+            }
+
+            window
+                .emit("add_before", center)
+                .unwrap();
+
+            // the trigger message arrives late and frames queue up, so the shot is the frame
+            // captured closest to the trigger rather than the one being processed
+            let shot_point = frame_state.trigger_time.and_then(|trigger_time| closest_frame(&frame_state.recent, trigger_time));
+            if let Some(shot_point) = shot_point {
+                // frames captured after the trigger follow the shot
+                let split = frame_state.before_trace
+                    .iter()
+                    .position(|point| point.time > shot_point.time)
+                    .unwrap_or(frame_state.before_trace.len());
+                let after_trace = frame_state.before_trace.split_off(split);
+                frame_state.shot_point = Some(shot_point);
+                frame_state.trigger_time = None;
+
+                window
+                    .emit("add_after", shot_point)
+                    .unwrap();
+                window
+                    .emit("add_shot", shot_point)
+                    .unwrap();
+                for point in after_trace.iter() {
                     window
-                        .emit("clear_trace", {})
+                        .emit("add_after", *point)
                         .unwrap();
-
-                    frame_state.shot_start_time = curr_time;
-                } 
-            } else {
-                if frame_state.shot_point.is_none() {
-                    if frame_state.trigger_time.is_some() {
-                        // TODO: comment when adding back splines
-                        frame_state.before_trace.push(center);
-                        
-                        window
-                            .emit("add_before", center)
-                            .unwrap();
-
-                        frame_state.shot_point = Some(center);
-                        // TODO: remove comment when adding back splines
-                        // trigger has just been pulled
-                        // if frame_state.trigger_time.unwrap() > curr_time {
-                        //     // trigger was after frame was taken
-                        //     // add current position to before trace
-                        //     frame_state.before_trace.push(center);
-                        //     
-                        //     window
-                        //         .emit("add_before", center)
-                        //         .unwrap();
-                        // } else {
-                        //     // trigger was before frame was taken
-                        //     // add current position to after trace
-                        //     frame_state.after_trace.push(center);
-
-                        //     window
-                        //         .emit("add_after", center)
-                        //         .unwrap();
-                        // }
-                    } else {
-                        frame_state.before_trace.push(center);
-
-                        window
-                            .emit("add_before", center)
-                            .unwrap();
-                    }
-                } else {
-                    if frame_state.after_trace.len() < 2 {
-                        frame_state.after_trace.push(center);
-                    } else if frame_state.after_trace.len() == 2 {
-                        frame_state.after_trace.push(center);
-
-                        // TODO: comment when adding back splines
-                        let trigger_time_from_shot_start = frame_state.before_trace.last().unwrap().time;
-                        let interp_x = frame_state.before_trace.last().unwrap().x;
-                        let interp_y = frame_state.before_trace.last().unwrap().y;
-
-                        // TODO: remove comment when adding back splines
-                        // let mut t_x = Vec::new();
-                        // let mut t_y = Vec::new();
-                        // for i in frame_state.before_trace.len() - 3..frame_state.before_trace.len() {
-                        //     let time = frame_state.before_trace[i].time;
-                        //     t_x.push((frame_state.before_trace[i].x, time));
-                        //     t_y.push((frame_state.before_trace[i].y, time));
-                        // }
-
-                        // for i in 0..3 {
-                        //     let time = frame_state.after_trace[i].time;
-                        //     t_x.push((frame_state.after_trace[i].x, time));
-                        //     t_y.push((frame_state.after_trace[i].y, time));
-                        // }
-
-                        // let sx = Spline::new(t_x, BoundaryCondition::Natural);
-                        // let sy = Spline::new(t_y, BoundaryCondition::Natural);
-
-                        // let trigger_time_from_shot_start = frame_state.trigger_time.unwrap().duration_since(frame_state.shot_start_time).as_secs_f64();
-                        // let interp_x = sx.eval(trigger_time_from_shot_start);
-                        // let interp_y = sy.eval(trigger_time_from_shot_start);
-
-                        let shot_point = TracePoint {
-                            x: interp_x,
-                            y: interp_y,
-                            time: trigger_time_from_shot_start,
-                        };
-                        frame_state.shot_point = Some(shot_point);
-
-                        window
-                            .emit("add_before", shot_point)
-                            .unwrap();
-                        window
-                            .emit("add_after", shot_point)
-                            .unwrap();
-                        window
-                            .emit("add_shot", shot_point)
-                            .unwrap();
-
-                        frame_state.trigger_time = None;
-                    } else {
-                        frame_state.after_trace.push(center);
-                        window
-                            .emit("add_after", center)
-                            .unwrap();
-                    }
                 }
+                frame_state.after_trace = after_trace;
             }
+        } else {
+            frame_state.after_trace.push(center);
+            window
+                .emit("add_after", center)
+                .unwrap();
         }
+    }
 
-        if !frame_state.shot_started {
-            // eitherways reset triggered value
-            // if shot has not been started
-            frame_state.trigger_time = None;
-        }
-        
-        frame_state.frame_index += 1;
+    if !frame_state.shot_started {
+        // eitherways reset triggered value
+        // if shot has not been started
+        frame_state.trigger_time = None;
+    }
+    
+    frame_state.frame_index += 1;
 
-        return true; // continue onto next frame
+    return true; // continue onto next frame
+}
+
+pub fn grab_shoot_frames<E: EventSink>(
+    mut source: Box<dyn FrameSource>,
+    mapping: TargetMapping,
+    fine_adjust: [f64; 2],
+    min_thresh: u32,
+    max_thresh: u32,
+    polarity: Polarity,
+    detector_kind: DetectorKind,
+    detector_params: DetectorParams,
+    undistorter: Option<Undistorter>,
+    // trace smoothing from 0 (off) to 1
+    smoothing: f64,
+    // follow lighting changes with the detector thresholds
+    adaptive: bool,
+    // folder to write frames of failed detections to
+    debug_dir: Option<PathBuf>,
+    up_down: bool,
+    record_path: Option<PathBuf>,
+    trigger_rx: Receiver<Instant>,
+    window: E,
+    rx: Receiver<()>,
+) {
+    // define and initialize frame state
+    let detector = Detector::new(detector_kind, detector_params, polarity, min_thresh, max_thresh);
+
+    // record the session alongside shooting if a path was given
+    let marker_tx = match record_path {
+        Some(path) => {
+            let (marker_tx, markers) = channel();
+            if source.record(Recording { path, markers }) {
+                Some(marker_tx)
+            } else {
+                error!("Source cannot be recorded");
+                None
+            }
+        }
+        None => None,
     };
+    let lighting = if adaptive { Some(AdaptiveThresholds::new(min_thresh, max_thresh)) } else { None };
+    let frame_state = FrameState::new(mapping, fine_adjust, up_down, undistorter, detector, Smoother::new(smoothing), lighting, debug_dir.map(DebugCapture::new), trigger_rx, marker_tx);
 
-    match camera_stream(label, rx, frame_state, grab_frame, window) {
+    match camera_stream(source, rx, frame_state, grab_shoot_frame::<E>, window) {
         Ok(()) => (),
         Err(e) => {
             error!("Could not read frames from camera ({:})", e.to_string());
@@ -423,3 +508,156 @@ pub fn grab_shoot_frames(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orientation::Orientation;
+    use crate::source::{EventLog, FrameFormat};
+    use crate::synthetic::{SyntheticConfig, SyntheticSource};
+    use serde_json::Value;
+
+    // frames are generated as fast as they are read, every run renders the same frames
+    fn synthetic_source(trigger_tx: Option<Sender<Instant>>) -> SyntheticSource {
+        let config = SyntheticConfig { fps: 60.0, noise: 2.0, seed: 7, realtime: false, ..SyntheticConfig::default() };
+        SyntheticSource::new(config, FrameFormat::Gray, trigger_tx)
+    }
+
+    // the synthetic source draws the target center in the middle of the frame at the default scale
+    fn synthetic_mapping() -> TargetMapping {
+        TargetMapping::from_calibration((640.0, 360.0), RATIO1, Orientation::default())
+    }
+
+    fn trace_point(value: &Value) -> (f64, f64, f64) {
+        (value["x"].as_f64().unwrap(), value["y"].as_f64().unwrap(), value["time"].as_f64().unwrap())
+    }
+
+    #[test]
+    fn synthetic_aim_maps_onto_its_scripted_position() {
+        let mut source = synthetic_source(None);
+        let (_tx, rx) = channel();
+        let mapping = synthetic_mapping();
+        let mut detector = Detector::new(DetectorKind::Contour, DetectorParams::default(), Polarity::Dark, 120, 150);
+        let mut tracker = Tracker::new();
+
+        // halfway through the approach, 0.5s in, the aim is 30mm above the target center
+        let mut aim = None;
+        for _ in 0..=30 {
+            let frame = source.next_frame(&rx).unwrap().unwrap();
+            let (cropped_frame, crop) = crop_frame(&frame.mat, &mapping);
+            let detections: Vec<Detection> = detector
+                .detect(&cropped_frame)
+                .into_iter()
+                .map(|detection| Detection { x: detection.x + crop.x as f64, y: detection.y + crop.y as f64, ..detection })
+                .collect();
+            aim = tracker.update(&detections, frame.time.pts);
+        }
+
+        let aim = aim.expect("aim was not tracked");
+        assert!(!aim.interpolated);
+        let (x, y) = mapping.to_target((aim.x, aim.y)).unwrap();
+        assert!(x.abs() < 1.0 && (y - 30.0).abs() < 1.0, "aim mapped to ({:}, {:})", x, y);
+    }
+
+    // runs the scripted shot to its end, the trigger reaches the frame state trigger_delay frames
+    // after the frame it was pulled on, returns when the shot started and finished
+    fn synthetic_shot(trigger_delay: usize, events: &EventLog) -> (f64, f64) {
+        let (trigger_tx, source_trigger_rx) = channel();
+        let mut source = synthetic_source(Some(trigger_tx));
+        let (_tx, rx) = channel();
+        let (delayed_trigger_tx, trigger_rx) = channel();
+        let detector = Detector::new(DetectorKind::Blob, DetectorParams::default(), Polarity::Dark, 120, 150);
+        let mut frame_state = FrameState::new(synthetic_mapping(), [0.0, 0.0], true, None, detector, Smoother::new(0.0), None, None, trigger_rx, None);
+
+        let mut pending: Option<(Instant, usize)> = None;
+        let mut started_at = None;
+        let mut finished_at = None;
+        while finished_at.is_none() {
+            let frame = source.next_frame(&rx).unwrap().unwrap();
+            let pts = frame.time.pts;
+            assert!(pts < 6.0, "shot did not finish");
+
+            if let Ok(trigger_time) = source_trigger_rx.try_recv() {
+                pending = Some((trigger_time, 0));
+            }
+            if let Some((trigger_time, frames)) = pending {
+                if frames == trigger_delay {
+                    delayed_trigger_tx.send(trigger_time).unwrap();
+                    pending = None;
+                } else {
+                    pending = Some((trigger_time, frames + 1));
+                }
+            }
+
+            assert!(grab_shoot_frame(frame.mat, frame.time, &mut frame_state, events));
+
+            if frame_state.shot_started && started_at.is_none() {
+                started_at = Some(pts);
+            }
+            if !events.payloads("shot_finished").is_empty() {
+                finished_at = Some(pts);
+            }
+        }
+
+        return (started_at.unwrap(), finished_at.unwrap());
+    }
+
+    #[test]
+    fn synthetic_shot_is_traced_from_start_to_finish() {
+        let events = EventLog::default();
+        let (started_at, finished_at) = synthetic_shot(0, &events);
+
+        // the aim comes down from 120mm and the shot starts once it passes the top edge of the
+        // target at 85mm, about 0.16s in
+        assert!(started_at > 0.14 && started_at < 0.2, "shot started at {:}s", started_at);
+        assert!(!events.payloads("clear_trace").is_empty());
+
+        // the trigger is pulled at the end of the hold, 4.5s into the script, on the held aim
+        let shots = events.payloads("add_shot");
+        assert_eq!(shots.len(), 1);
+        let (x, y, time) = trace_point(&shots[0]);
+        assert!(x.abs() < 3.0 && y.abs() < 3.0, "shot at ({:}, {:})", x, y);
+        assert!((time - (4.5 - started_at)).abs() <= 1.5 / 60.0, "shot at {:}s", time);
+
+        // the shot finishes 1s after the trigger with the trace leading up to it and following it
+        let finished = &events.payloads("shot_finished")[0];
+        assert!((finished_at - started_at - time - 1.0).abs() <= 1.5 / 60.0, "shot finished at {:}s", finished_at);
+        assert_eq!(finished["shot_point"], shots[0]);
+
+        let before_trace = finished["before_trace"].as_array().unwrap();
+        assert!(before_trace.len() > 200);
+        assert!(trace_point(&before_trace[0]).2 < 0.05);
+        assert!(before_trace.windows(2).all(|pair| trace_point(&pair[1]).2 > trace_point(&pair[0]).2));
+        assert_eq!(before_trace.last().unwrap(), &shots[0]);
+
+        let after_trace = finished["after_trace"].as_array().unwrap();
+        assert!(after_trace.len() >= 55, "{:} points after the shot", after_trace.len());
+        assert!(after_trace.iter().all(|point| {
+            let after_time = trace_point(point).2;
+            after_time > time && after_time <= time + 1.0 + 1.5 / 60.0
+        }));
+    }
+
+    #[test]
+    fn late_trigger_picks_the_frame_it_was_pulled_on() {
+        let on_time = EventLog::default();
+        synthetic_shot(0, &on_time);
+        let on_time_shot = on_time.payloads("add_shot")[0].clone();
+
+        // the trigger arrives 0.2s late, by then the aim has moved on into the follow through
+        let late = EventLog::default();
+        let (started_at, _) = synthetic_shot(12, &late);
+        let shots = late.payloads("add_shot");
+        assert_eq!(shots.len(), 1);
+        assert_eq!(shots[0], on_time_shot);
+        let (_, _, time) = trace_point(&shots[0]);
+        assert!((time - (4.5 - started_at)).abs() <= 1.5 / 60.0, "shot at {:}s", time);
+
+        // the frames processed while the trigger was on its way are moved after the shot
+        let finished = &late.payloads("shot_finished")[0];
+        assert_eq!(finished["before_trace"].as_array().unwrap().last().unwrap(), &shots[0]);
+        let after_trace = finished["after_trace"].as_array().unwrap();
+        assert!(after_trace.iter().all(|point| trace_point(point).2 > time));
+        assert_eq!(late.payloads("add_after").len(), on_time.payloads("add_after").len());
+    }
+}
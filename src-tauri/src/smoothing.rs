@@ -0,0 +1,99 @@
+// measurement noise in mm at full strength, how much of the detected movement is taken for jitter
+static MAX_MEASUREMENT_NOISE: f64 = 2.0;
+// how quickly the aim is expected to change speed, in mm/s²
+static ACCELERATION_NOISE: f64 = 500.0;
+// seconds without a position after which the filter starts over instead of predicting across
+static MAX_GAP: f64 = 0.25;
+
+// constant velocity kalman filter along one axis
+#[derive(Clone, Copy)]
+struct AxisFilter {
+    position: f64,
+    velocity: f64,
+    covariance: [[f64; 2]; 2],
+}
+
+impl AxisFilter {
+    fn new(position: f64, measurement_noise: f64) -> AxisFilter {
+        AxisFilter {
+            position,
+            velocity: 0.0,
+            covariance: [[measurement_noise, 0.0], [0.0, ACCELERATION_NOISE * ACCELERATION_NOISE]],
+        }
+    }
+
+    fn predict(&mut self, dt: f64) {
+        self.position += self.velocity * dt;
+
+        let p = self.covariance;
+        let q = ACCELERATION_NOISE * ACCELERATION_NOISE;
+        self.covariance = [
+            [
+                p[0][0] + dt * (p[1][0] + p[0][1]) + dt * dt * p[1][1] + q * dt.powi(4) / 4.0,
+                p[0][1] + dt * p[1][1] + q * dt.powi(3) / 2.0,
+            ],
+            [
+                p[1][0] + dt * p[1][1] + q * dt.powi(3) / 2.0,
+                p[1][1] + q * dt * dt,
+            ],
+        ];
+    }
+
+    fn correct(&mut self, measured: f64, measurement_noise: f64) {
+        let p = self.covariance;
+        let innovation = measured - self.position;
+        let gain = [p[0][0] / (p[0][0] + measurement_noise), p[1][0] / (p[0][0] + measurement_noise)];
+
+        self.position += gain[0] * innovation;
+        self.velocity += gain[1] * innovation;
+        self.covariance = [
+            [(1.0 - gain[0]) * p[0][0], (1.0 - gain[0]) * p[0][1]],
+            [p[1][0] - gain[1] * p[0][0], p[1][1] - gain[1] * p[0][1]],
+        ];
+    }
+}
+
+// smooths the trace of the aim on the target
+pub struct Smoother {
+    // variance of a measurement in mm², 0 leaves positions untouched
+    measurement_noise: f64,
+    filters: Option<(AxisFilter, AxisFilter)>,
+    last_time: f64,
+}
+
+impl Smoother {
+    // `strength` from 0 (off) to 1
+    pub fn new(strength: f64) -> Smoother {
+        let noise = strength.max(0.0).min(1.0) * MAX_MEASUREMENT_NOISE;
+        Smoother { measurement_noise: noise * noise, filters: None, last_time: 0.0 }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.measurement_noise > 0.0
+    }
+
+    // smoothed position for a position on the target at `time` seconds, an interpolated position
+    // only moves the filter on without being trusted as a measurement
+    pub fn update(&mut self, position: (f64, f64), time: f64, interpolated: bool) -> (f64, f64) {
+        if !self.enabled() {
+            return position;
+        }
+
+        let dt = time - self.last_time;
+        self.last_time = time;
+        if self.filters.is_none() || dt < 0.0 || dt > MAX_GAP {
+            self.filters = Some((AxisFilter::new(position.0, self.measurement_noise), AxisFilter::new(position.1, self.measurement_noise)));
+            return position;
+        }
+
+        let (x_filter, y_filter) = self.filters.as_mut().unwrap();
+        x_filter.predict(dt);
+        y_filter.predict(dt);
+        if !interpolated {
+            x_filter.correct(position.0, self.measurement_noise);
+            y_filter.correct(position.1, self.measurement_noise);
+        }
+
+        return (x_filter.position, y_filter.position);
+    }
+}
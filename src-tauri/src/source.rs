@@ -0,0 +1,380 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use ffmpeg::Error;
+use log::{error, info, warn};
+use opencv::core::{CV_8UC1, CV_8UC3};
+use opencv::imgcodecs::{imread, IMREAD_COLOR, IMREAD_GRAYSCALE};
+use opencv::imgproc::cvt_color;
+use opencv::prelude::*;
+use serde::Serialize;
+use tauri::Window;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use crate::camera::{is_camera_path, CaptureProfile, FfmpegSource, StallWatch};
+use crate::playback::PlaybackCommand;
+use crate::queue::{FrameQueue, Popped, StatsCounter};
+use crate::recorder::Recording;
+use crate::synthetic::SyntheticSource;
+
+static IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "bmp", "tiff"];
+static IMAGE_SEQUENCE_FPS: f64 = 30.0;
+
+// frames waiting for processing, older ones are dropped beyond this
+static QUEUE_CAPACITY: usize = 2;
+static STATS_INTERVAL: Duration = Duration::from_secs(1);
+// how often processing checks on the capture thread while no frames arrive
+static WATCHDOG_INTERVAL: Duration = Duration::from_millis(250);
+// how long past its stall deadline a capture thread has to be to count as stuck, the source
+// notices stalls itself in any read that returns
+static STUCK_AFTER: Duration = Duration::from_secs(1);
+// how long stopping waits for the capture thread
+static STOP_TIMEOUT: Duration = Duration::from_secs(3);
+
+// when a frame was captured
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTime {
+    // capture time in seconds from the source's timestamps, kept continuous across seeks and
+    // reconnects
+    pub pts: f64,
+    // wall clock estimate of the capture, for correlating with mic triggers
+    pub instant: Instant,
+}
+
+// pixel layout of the frames a source produces, detection only needs gray while previews
+// need colour
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameFormat {
+    Gray,
+    Rgb,
+}
+
+impl FrameFormat {
+    pub fn channels(&self) -> i32 {
+        match self {
+            FrameFormat::Gray => 1,
+            FrameFormat::Rgb => 3,
+        }
+    }
+
+    pub fn mat_type(&self) -> i32 {
+        match self {
+            FrameFormat::Gray => CV_8UC1,
+            FrameFormat::Rgb => CV_8UC3,
+        }
+    }
+}
+
+// gray or RGB frame, as requested from the source, and the time it was captured
+pub struct Frame {
+    pub mat: Mat,
+    pub time: FrameTime,
+}
+
+pub trait FrameSource: Send {
+    // block until the next frame is available, None once the source is exhausted or `rx`
+    // asked to terminate
+    fn next_frame(&mut self, rx: &Receiver<()>) -> Result<Option<Frame>, Error>;
+
+    // record the raw stream alongside reading it, returns false if the source cannot record
+    fn record(&mut self, _recording: Recording) -> bool {
+        false
+    }
+
+    // deadline of a source whose reads can block, None if they cannot
+    fn stall_watch(&self) -> Option<Arc<StallWatch>> {
+        None
+    }
+
+    // source for the same device to read from instead when this one's capture thread is stuck
+    fn reopen(&self) -> Option<Box<dyn FrameSource>> {
+        None
+    }
+}
+
+// where frame loops send their events to, the window in the app
+pub trait EventSink {
+    fn emit<S: Serialize + Clone>(&self, event: &str, payload: S) -> tauri::Result<()>;
+}
+
+impl EventSink for Window {
+    fn emit<S: Serialize + Clone>(&self, event: &str, payload: S) -> tauri::Result<()> {
+        Window::emit(self, event, payload)
+    }
+}
+
+// keeps the events sent to it in order, to check them in tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct EventLog(Arc<std::sync::Mutex<Vec<(String, serde_json::Value)>>>);
+
+#[cfg(test)]
+impl EventLog {
+    pub fn events(&self) -> Vec<(String, serde_json::Value)> {
+        self.0.lock().unwrap().clone()
+    }
+
+    // payloads of the events with the given name
+    pub fn payloads(&self, event: &str) -> Vec<serde_json::Value> {
+        self.events().into_iter().filter(|(name, _)| name == event).map(|(_, payload)| payload).collect()
+    }
+}
+
+#[cfg(test)]
+impl EventSink for EventLog {
+    fn emit<S: Serialize + Clone>(&self, event: &str, payload: S) -> tauri::Result<()> {
+        let payload = serde_json::to_value(payload)?;
+        self.0.lock().unwrap().push((event.to_string(), payload));
+        Ok(())
+    }
+}
+
+pub fn terminate_requested(rx: &Receiver<()>) -> bool {
+    match rx.try_recv() {
+        Ok(_) | Err(TryRecvError::Disconnected) => true,
+        Err(TryRecvError::Empty) => false,
+    }
+}
+
+// choose the source for a label from the UI, nothing is opened until the first frame is read
+pub fn open_source(label: &str, profile: CaptureProfile, format: FrameFormat, playback_rx: Receiver<PlaybackCommand>, trigger_tx: Option<Sender<Instant>>, window: Window) -> Box<dyn FrameSource> {
+    if let Some(script) = label.strip_prefix("synthetic:") {
+        // scripted triggers go to the same channel as mic triggers
+        Box::new(SyntheticSource::from_script(script, format, trigger_tx))
+    } else if Path::new(label).is_dir() {
+        Box::new(ImageSequenceSource::new(PathBuf::from(label), IMAGE_SEQUENCE_FPS, format))
+    } else if is_camera_path(label) {
+        Box::new(FfmpegSource::file(label.to_string(), profile, format, playback_rx, window))
+    } else {
+        Box::new(FfmpegSource::device(label.to_string(), profile, format, window))
+    }
+}
+
+// capture thread feeding a queue, with what is needed to replace it when it gets stuck
+struct Capture {
+    queue: Arc<FrameQueue>,
+    stop_tx: Sender<()>,
+    result_rx: Receiver<Result<(), Error>>,
+    watch: Option<Arc<StallWatch>>,
+    // opened in place of the source if the capture thread gets stuck
+    spare: Option<Box<dyn FrameSource>>,
+}
+
+impl Capture {
+    fn start(source: Box<dyn FrameSource>) -> Capture {
+        let queue = Arc::new(FrameQueue::new(QUEUE_CAPACITY));
+        let (stop_tx, stop_rx) = channel();
+        let (result_tx, result_rx) = channel();
+        let watch = source.stall_watch();
+        let spare = source.reopen();
+
+        // the thread is never joined, a stuck one is left behind rather than waited on forever
+        let capture_queue = queue.clone();
+        spawn(move || {
+            let _ = result_tx.send(capture_frames(source, stop_rx, capture_queue));
+        });
+
+        Capture { queue, stop_tx, result_rx, watch, spare }
+    }
+
+    // blocked in a read that the source's own stall detection cannot interrupt
+    fn is_stuck(&self) -> bool {
+        self.watch.as_ref().map(|watch| watch.stalled_for() > STUCK_AFTER).unwrap_or(false)
+    }
+
+    fn stop(self) -> Result<(), Error> {
+        let _ = self.stop_tx.send(());
+        self.queue.close();
+        if self.is_stuck() {
+            warn!("Capture thread is stuck, leaving it behind");
+            return Ok(());
+        }
+
+        match self.result_rx.recv_timeout(STOP_TIMEOUT) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("Capture thread did not stop in time, leaving it behind");
+                Ok(())
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                error!("Capture thread ended without a result");
+                Err(Error::Bug)
+            }
+        }
+    }
+}
+
+// reads frames on a capture thread and feeds them to `grab_frame` until either asks to stop,
+// frames are dropped rather than queued up when `grab_frame` cannot keep up. A capture thread
+// stuck in a device read is replaced by one reopening the device
+pub fn camera_stream<T, E: EventSink>(source: Box<dyn FrameSource>, rx: Receiver<()>, mut state: T, grab_frame: fn(Mat, FrameTime, &mut T, &E) -> bool, window: E) -> Result<(), Error> {
+    let mut capture = Capture::start(source);
+
+    let mut stats = StatsCounter::new();
+    loop {
+        if terminate_requested(&rx) {
+            info!("Terminating camera stream");
+            break;
+        }
+
+        let frame = match capture.queue.pop_timeout(WATCHDOG_INTERVAL) {
+            Popped::Frame(frame) => frame,
+            Popped::Closed => break,
+            Popped::Empty => {
+                if capture.is_stuck() {
+                    if let Some(spare) = capture.spare.take() {
+                        warn!("Capture thread is stuck, reopening the camera");
+                        stats.retire(&capture.queue);
+                        let stuck = std::mem::replace(&mut capture, Capture::start(spare));
+                        let _ = stuck.stop();
+                    }
+                }
+                continue;
+            }
+        };
+
+        stats.processing(&frame.time);
+        if !grab_frame(frame.mat, frame.time, &mut state, &window) {
+            break;
+        }
+        if let Some(payload) = stats.report(&capture.queue, STATS_INTERVAL) {
+            window.emit("frame_stats", payload).unwrap();
+        }
+    }
+
+    return capture.stop();
+}
+
+fn capture_frames(mut source: Box<dyn FrameSource>, rx: Receiver<()>, queue: Arc<FrameQueue>) -> Result<(), Error> {
+    let result = loop {
+        match source.next_frame(&rx) {
+            Ok(Some(frame)) => {
+                if !queue.push(frame) {
+                    break Ok(());
+                }
+            }
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+
+    // let processing drain what is left and stop
+    queue.close();
+    return result;
+}
+
+// images of a directory played back in name order at a fixed framerate
+pub struct ImageSequenceSource {
+    paths: Vec<PathBuf>,
+    fps: f64,
+    format: FrameFormat,
+    index: usize,
+    start: Option<Instant>,
+}
+
+impl ImageSequenceSource {
+    pub fn new(dir: PathBuf, fps: f64, format: FrameFormat) -> ImageSequenceSource {
+        let mut paths: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .and_then(|ext| ext.to_str())
+                        .map(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                        .unwrap_or(false)
+                })
+                .collect(),
+            Err(e) => {
+                error!("Could not read image sequence {:} ({:})", dir.display(), e);
+                Vec::new()
+            }
+        };
+        paths.sort();
+        info!("Image sequence {:} has {:} frames", dir.display(), paths.len());
+
+        ImageSequenceSource { paths, fps, format, index: 0, start: None }
+    }
+}
+
+impl FrameSource for ImageSequenceSource {
+    fn next_frame(&mut self, rx: &Receiver<()>) -> Result<Option<Frame>, Error> {
+        while self.index < self.paths.len() {
+            if terminate_requested(rx) {
+                info!("Terminating image sequence");
+                return Ok(None);
+            }
+
+            let path = &self.paths[self.index];
+            let pts = self.index as f64 / self.fps;
+            self.index += 1;
+
+            // pace frames in real time so wall clock based consumers behave as with a camera
+            let start = *self.start.get_or_insert_with(Instant::now);
+            let due = start + Duration::from_secs_f64(pts);
+            let now = Instant::now();
+            if due > now {
+                sleep(due - now);
+            }
+
+            let flags = match self.format {
+                FrameFormat::Gray => IMREAD_GRAYSCALE,
+                FrameFormat::Rgb => IMREAD_COLOR,
+            };
+            let image = match imread(&path.to_string_lossy(), flags) {
+                Ok(mat) if mat.rows() > 0 => mat,
+                _ => {
+                    warn!("Could not read image {:}", path.display());
+                    continue;
+                }
+            };
+            let mat = match self.format {
+                FrameFormat::Gray => image,
+                FrameFormat::Rgb => {
+                    let mut mat = Mat::default();
+                    if let Err(e) = cvt_color(&image, &mut mat, opencv::imgproc::COLOR_BGR2RGB, 0) {
+                        warn!("Could not convert image {:} ({:})", path.display(), e);
+                        continue;
+                    }
+                    mat
+                }
+            };
+
+            return Ok(Some(Frame { mat, time: FrameTime { pts, instant: due.max(now) } }));
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{SyntheticConfig, SyntheticSource};
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn camera_stream_sends_events_to_the_sink() {
+        let config = SyntheticConfig { width: 320, height: 240, realtime: false, ..SyntheticConfig::default() };
+        let source = Box::new(SyntheticSource::new(config, FrameFormat::Gray, None));
+        let (_tx, rx) = channel();
+        let events = EventLog::default();
+
+        let grab_frame = |_frame: Mat, frame_time: FrameTime, frames: &mut u32, events: &EventLog| -> bool {
+            events.emit("frame", frame_time.pts).unwrap();
+            *frames += 1;
+            return *frames < 10;
+        };
+        camera_stream(source, rx, 0, grab_frame, events.clone()).unwrap();
+
+        // frames are processed in capture order, dropped ones are skipped
+        let pts: Vec<f64> = events.payloads("frame").iter().map(|pts| pts.as_f64().unwrap()).collect();
+        assert_eq!(pts.len(), 10);
+        assert!(pts.windows(2).all(|pair| pair[1] > pair[0]));
+    }
+}
@@ -0,0 +1,261 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use ffmpeg::Error;
+use log::{error, info, warn};
+use opencv::core::{Point, Scalar, Size, BORDER_DEFAULT};
+use opencv::imgproc::{circle, gaussian_blur, FILLED, LINE_AA};
+use opencv::prelude::*;
+
+use std::f64::consts::PI;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::shoot::RATIO1;
+use crate::source::{terminate_requested, Frame, FrameFormat, FrameSource, FrameTime};
+
+// phases of one scripted shot, in seconds
+const APPROACH: f64 = 1.0;
+const WOBBLE: f64 = 2.0;
+const HOLD: f64 = 1.5;
+const FOLLOW_THROUGH: f64 = 1.0;
+const REST: f64 = 1.5;
+const CYCLE: f64 = APPROACH + WOBBLE + HOLD + FOLLOW_THROUGH + REST;
+
+// aim starts this far above the centre (mm) and is lifted out of the target after a shot
+static APPROACH_FROM: f64 = 120.0;
+static REST_AT: f64 = 250.0;
+
+// settings of a synthetic source, parsed from "key=value" pairs separated by commas
+#[derive(Clone, Debug)]
+pub struct SyntheticConfig {
+    pub width: i32,
+    pub height: i32,
+    pub fps: f64,
+    // mm per pixel, used to turn the scripted trajectory into pixels
+    pub scale: f64,
+    pub marker_radius: i32,
+    // standard deviation of per pixel gaussian noise
+    pub noise: f64,
+    // gaussian blur kernel size, 0 disables
+    pub blur: i32,
+    // fraction the brightness falls off across the frame, left to right
+    pub gradient: f64,
+    // bright marker on a dark background, as seen by IR cameras
+    pub invert: bool,
+    pub seed: u64,
+    // pace frames in real time, otherwise frames are produced as fast as they are read
+    pub realtime: bool,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> SyntheticConfig {
+        SyntheticConfig {
+            width: 1280,
+            height: 720,
+            fps: 120.0,
+            scale: RATIO1,
+            // aiming black of an air pistol target at the default scale
+            marker_radius: 44,
+            noise: 0.0,
+            blur: 0,
+            gradient: 0.0,
+            invert: false,
+            seed: 1,
+            realtime: true,
+        }
+    }
+}
+
+impl SyntheticConfig {
+    pub fn parse(script: &str) -> SyntheticConfig {
+        let mut config = SyntheticConfig::default();
+        for pair in script.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = match pair.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    warn!("Ignoring synthetic option without value {:}", pair);
+                    continue;
+                }
+            };
+
+            let parsed = match key {
+                "width" => value.parse().map(|v| config.width = v).is_ok(),
+                "height" => value.parse().map(|v| config.height = v).is_ok(),
+                "fps" => value.parse().map(|v| config.fps = v).is_ok(),
+                "scale" => value.parse().map(|v| config.scale = v).is_ok(),
+                "radius" => value.parse().map(|v| config.marker_radius = v).is_ok(),
+                "noise" => value.parse().map(|v| config.noise = v).is_ok(),
+                "blur" => value.parse().map(|v| config.blur = v).is_ok(),
+                "gradient" => value.parse().map(|v| config.gradient = v).is_ok(),
+                "invert" => value.parse().map(|v: u8| config.invert = v != 0).is_ok(),
+                "seed" => value.parse().map(|v| config.seed = v).is_ok(),
+                "realtime" => value.parse().map(|v: u8| config.realtime = v != 0).is_ok(),
+                _ => false,
+            };
+            if !parsed {
+                warn!("Ignoring synthetic option {:}={:}", key, value);
+            }
+        }
+
+        return config;
+    }
+}
+
+// small deterministic generator so runs with the same seed render the same frames
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn gaussian(&mut self) -> f64 {
+        let u1 = self.next_f64().max(f64::MIN_POSITIVE);
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+}
+
+// scripted aim position in target mm (x right, y up) at `t` seconds into a shot cycle
+fn trajectory(t: f64, jitter: (f64, f64)) -> (f64, f64) {
+    let ease = |p: f64| 1.0 - (1.0 - p.min(1.0).max(0.0)).powi(2);
+
+    if t < APPROACH {
+        // come down onto the target from above
+        return (0.0, APPROACH_FROM * (1.0 - ease(t / APPROACH)));
+    }
+
+    let t = t - APPROACH;
+    if t < WOBBLE {
+        // settle with a decaying wobble
+        let amplitude = 8.0 * (1.0 - t / WOBBLE) + 2.0;
+        return (amplitude * (2.0 * PI * 1.5 * t).sin(), amplitude * (2.0 * PI * 2.0 * t).cos() - amplitude);
+    }
+
+    let t = t - WOBBLE;
+    if t < HOLD {
+        // hold with a little tremor
+        return (jitter.0, jitter.1);
+    }
+
+    let t = t - HOLD;
+    if t < FOLLOW_THROUGH {
+        // recoil lifts the aim a little and it drifts back
+        let lift = 4.0 * (PI * t / FOLLOW_THROUGH).sin();
+        return (jitter.0 + 0.5 * lift, jitter.1 + lift);
+    }
+
+    // lift out of the target and wait for the next shot
+    let t = t - FOLLOW_THROUGH;
+    return (0.0, REST_AT * ease(t / (0.3 * REST)));
+}
+
+// generated frames of a black aiming marker on a white background, or inverted, following a
+// scripted shot
+pub struct SyntheticSource {
+    config: SyntheticConfig,
+    format: FrameFormat,
+    trigger_tx: Option<Sender<Instant>>,
+    rng: XorShift,
+    frame_index: u64,
+    start: Option<Instant>,
+}
+
+impl SyntheticSource {
+    pub fn new(config: SyntheticConfig, format: FrameFormat, trigger_tx: Option<Sender<Instant>>) -> SyntheticSource {
+        let rng = XorShift(config.seed.max(1));
+        SyntheticSource { config, format, trigger_tx, rng, frame_index: 0, start: None }
+    }
+
+    // source described by the part of a "synthetic:" label after the prefix
+    pub fn from_script(script: &str, format: FrameFormat, trigger_tx: Option<Sender<Instant>>) -> SyntheticSource {
+        let config = SyntheticConfig::parse(script);
+        info!("Starting synthetic source {:?}", config);
+        SyntheticSource::new(config, format, trigger_tx)
+    }
+
+    fn render(&mut self, position: (f64, f64)) -> Result<Mat, opencv::Error> {
+        let config = &self.config;
+        let (background, marker) = if config.invert { (0.0, 255.0) } else { (255.0, 0.0) };
+        let mut mat = Mat::new_rows_cols_with_default(config.height, config.width, self.format.mat_type(), Scalar::all(background))?;
+
+        // same camera mounting as the shoot pipeline: target up is +x in pixels,
+        // target right is -y in pixels
+        let center = Point {
+            x: (config.width as f64 / 2.0 + position.1 / config.scale).round() as i32,
+            y: (config.height as f64 / 2.0 - position.0 / config.scale).round() as i32,
+        };
+        circle(&mut mat, center, config.marker_radius, Scalar::all(marker), FILLED, LINE_AA, 0)?;
+
+        if config.blur > 0 {
+            // kernel sizes have to be odd
+            let size = config.blur | 1;
+            let mut blurred = Mat::default();
+            gaussian_blur(&mat, &mut blurred, Size { width: size, height: size }, 0.0, 0.0, BORDER_DEFAULT)?;
+            mat = blurred;
+        }
+
+        if config.noise > 0.0 || config.gradient > 0.0 {
+            let noise = config.noise;
+            let gradient = config.gradient;
+            let width = config.width as usize;
+            let channels = self.format.channels() as usize;
+            let rng = &mut self.rng;
+            for (i, value) in mat.data_bytes_mut()?.iter_mut().enumerate() {
+                let x = (i / channels) % width;
+                let light = 1.0 - gradient * x as f64 / width as f64;
+                let noisy = *value as f64 * light + if noise > 0.0 { noise * rng.gaussian() } else { 0.0 };
+                *value = noisy.round().max(0.0).min(255.0) as u8;
+            }
+        }
+
+        Ok(mat)
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn next_frame(&mut self, rx: &Receiver<()>) -> Result<Option<Frame>, Error> {
+        if terminate_requested(rx) {
+            info!("Terminating synthetic source");
+            return Ok(None);
+        }
+
+        let frame_interval = 1.0 / self.config.fps;
+        let pts = self.frame_index as f64 * frame_interval;
+        self.frame_index += 1;
+
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let due = start + Duration::from_secs_f64(pts);
+        if self.config.realtime {
+            let now = Instant::now();
+            if due > now {
+                sleep(due - now);
+            }
+        }
+
+        // trigger is pulled at the end of the hold of every cycle
+        let trigger_at = APPROACH + WOBBLE + HOLD;
+        let cycle_time = pts % CYCLE;
+        if cycle_time >= trigger_at && cycle_time - frame_interval < trigger_at {
+            let trigger_instant = due - Duration::from_secs_f64(cycle_time - trigger_at);
+            info!("Synthetic trigger at {:}s", pts - (cycle_time - trigger_at));
+            if let Some(trigger_tx) = self.trigger_tx.as_ref() {
+                let _ = trigger_tx.send(trigger_instant);
+            }
+        }
+
+        let jitter = (0.5 * self.rng.gaussian(), 0.5 * self.rng.gaussian());
+        let position = trajectory(cycle_time, jitter);
+        match self.render(position) {
+            Ok(mat) => Ok(Some(Frame { mat, time: FrameTime { pts, instant: due } })),
+            Err(e) => {
+                error!("Could not render synthetic frame ({:})", e);
+                Err(Error::Bug)
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use crate::detector::Detection;
+
+// seconds of missed detections a track is bridged over before it is lost
+static MAX_GAP: f64 = 0.1;
+// furthest a detection may be from the predicted position to continue the track, in marker
+// diameters so it does not depend on how far the camera is from the target
+static GATE: f64 = 1.5;
+// weight of the newest motion in the velocity estimate
+static VELOCITY_SMOOTHING: f64 = 0.5;
+
+// position of the marker for a frame, in pixels of the frame
+#[derive(Clone, Copy, Debug)]
+pub struct Tracked {
+    pub x: f64,
+    pub y: f64,
+    // diameter
+    pub size: f64,
+    pub confidence: f64,
+    // predicted from recent motion because the marker was not detected
+    pub interpolated: bool,
+}
+
+// follows the marker with a constant velocity model, picking the detection closest to where the
+// marker is expected when there are several and bridging short gaps without any
+pub struct Tracker {
+    // last detected marker and when it was detected
+    last: Option<(Detection, f64)>,
+    // pixels per second
+    velocity: (f64, f64),
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker { last: None, velocity: (0.0, 0.0) }
+    }
+
+    fn predict(&self, time: f64) -> Option<(f64, f64)> {
+        let (last, last_time) = self.last?;
+        let dt = time - last_time;
+        return Some((last.x + self.velocity.0 * dt, last.y + self.velocity.1 * dt));
+    }
+
+    fn accept(&mut self, detection: Detection, time: f64) -> Tracked {
+        if let Some((last, last_time)) = self.last {
+            let dt = time - last_time;
+            if dt > 0.0 {
+                let velocity = ((detection.x - last.x) / dt, (detection.y - last.y) / dt);
+                self.velocity = (
+                    self.velocity.0 + VELOCITY_SMOOTHING * (velocity.0 - self.velocity.0),
+                    self.velocity.1 + VELOCITY_SMOOTHING * (velocity.1 - self.velocity.1),
+                );
+            }
+        } else {
+            self.velocity = (0.0, 0.0);
+        }
+        self.last = Some((detection, time));
+
+        return Tracked {
+            x: detection.x,
+            y: detection.y,
+            size: detection.size,
+            confidence: detection.confidence,
+            interpolated: false,
+        };
+    }
+
+    // marker position for the frame captured at `time` (seconds) with `detections`, None if the
+    // marker is lost
+    pub fn update(&mut self, detections: &[Detection], time: f64) -> Option<Tracked> {
+        // a track not seen for too long is dropped and picked up again from scratch
+        if self.last.map(|(_, last_time)| time - last_time > MAX_GAP).unwrap_or(false) {
+            self.last = None;
+        }
+
+        let (last, _) = match self.last {
+            Some(last) => last,
+            None => {
+                // without a track the most convincing detection starts one
+                let best = detections.iter().max_by(|a, b| a.confidence.partial_cmp(&b.confidence).unwrap())?;
+                return Some(self.accept(*best, time));
+            }
+        };
+
+        let predicted = self.predict(time).unwrap();
+        let distance = |detection: &Detection| (detection.x - predicted.0).hypot(detection.y - predicted.1);
+        let closest = detections
+            .iter()
+            .filter(|detection| distance(detection) <= GATE * last.size)
+            .min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap());
+
+        match closest {
+            Some(detection) => Some(self.accept(*detection, time)),
+            None => Some(Tracked {
+                x: predicted.0,
+                y: predicted.1,
+                size: last.size,
+                confidence: 0.0,
+                interpolated: true,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FPS: f64 = 60.0;
+    static SIZE: f64 = 40.0;
+
+    fn detection((x, y): (f64, f64), confidence: f64) -> Detection {
+        Detection { x, y, size: SIZE, confidence }
+    }
+
+    // scripted marker moving right at 300 pixels per second
+    fn marker_at(frame: u32) -> (f64, f64) {
+        (400.0 + 300.0 * frame as f64 / FPS, 300.0)
+    }
+
+    fn time(frame: u32) -> f64 {
+        frame as f64 / FPS
+    }
+
+    fn assert_at(tracked: Option<Tracked>, position: (f64, f64), tolerance: f64, interpolated: bool) {
+        let tracked = tracked.expect("marker was lost");
+        assert_eq!(tracked.interpolated, interpolated, "{:?}", tracked);
+        assert!(
+            (tracked.x - position.0).abs() <= tolerance && (tracked.y - position.1).abs() <= tolerance,
+            "{:?} is not at {:?}", tracked, position
+        );
+    }
+
+    // follows the scripted marker over `frames` from the first frame
+    fn track(tracker: &mut Tracker, frames: std::ops::Range<u32>) {
+        for frame in frames {
+            assert_at(tracker.update(&[detection(marker_at(frame), 0.8)], time(frame)), marker_at(frame), 0.0, false);
+        }
+    }
+
+    #[test]
+    fn track_keeps_to_the_marker_over_other_detections() {
+        let mut tracker = Tracker::new();
+        track(&mut tracker, 0..5);
+
+        // a more convincing false detection elsewhere does not take over the track
+        for frame in 5..30 {
+            let detections = [detection((900.0, 100.0), 1.0), detection(marker_at(frame), 0.8)];
+            assert_at(tracker.update(&detections, time(frame)), marker_at(frame), 0.0, false);
+        }
+    }
+
+    #[test]
+    fn detections_outside_the_gate_are_not_followed() {
+        let mut tracker = Tracker::new();
+        track(&mut tracker, 0..20);
+
+        // the marker is missed and a detection two diameters ahead shows up instead, the track
+        // is predicted on from the marker's motion
+        for frame in 20..24 {
+            let (x, y) = marker_at(frame);
+            let tracked = tracker.update(&[detection((x + 2.0 * SIZE, y), 0.9)], time(frame));
+            assert_at(tracked, marker_at(frame), 2.0, true);
+            assert_eq!(tracked.unwrap().confidence, 0.0);
+        }
+
+        // within the gate the marker is picked up again
+        let (x, y) = marker_at(24);
+        assert_at(tracker.update(&[detection((x + 0.5 * SIZE, y), 0.9)], time(24)), (x + 0.5 * SIZE, y), 0.0, false);
+    }
+
+    #[test]
+    fn track_is_lost_after_a_long_gap_and_starts_over() {
+        let mut tracker = Tracker::new();
+        track(&mut tracker, 0..20);
+
+        // short gaps are bridged with predictions
+        for frame in 20..25 {
+            assert_at(tracker.update(&[], time(frame)), marker_at(frame), 2.0, true);
+        }
+
+        // beyond the longest gap the marker is lost
+        assert!(tracker.update(&[], time(26)).is_none());
+
+        // and picked up wherever it shows up next, without the old motion
+        assert_at(tracker.update(&[detection((200.0, 500.0), 0.8)], time(27)), (200.0, 500.0), 0.0, false);
+        assert_at(tracker.update(&[], time(28)), (200.0, 500.0), 0.0, true);
+    }
+}
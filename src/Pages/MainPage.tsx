@@ -9,6 +9,7 @@ import ScoreStatCard from "./components/ScoreStatCard";
 import { invoke } from "@tauri-apps/api/tauri";
 import ShotTable from "./components/ShotTable";
 import LineChart from "./components/LineChart";
+import { WebcamInfo } from "./components/Webcam";
 // import doneSound from 'public/sounds/done.mp3';
 // import useSound from 'use-sound';
 
@@ -54,12 +55,16 @@ export default function MainPage() {
   const [data, setData] = useState<{ x: number; y: number }[][]>([]);
 
   // user options
-  const [webcams, setWebcams] = useState<string[]>([]);
+  const [webcams, setWebcams] = useState<WebcamInfo[]>([]);
   const [mics, setMics] = useState<string[]>([]);
   const [cameraId, setCameraId] = useState("");
   const [micId, setMicId] = useState("");
   const [micThresh, setMicThresh] = useState(0.2);
   const [cameraThreshs, setCameraThreshs] = useState<number[]>([120, 150]);
+  const [recordPath, setRecordPath] = useState<string | null>(null);
+  const [smoothing, setSmoothing] = useState(0.5);
+  const [adaptiveThresholds, setAdaptiveThresholds] = useState(false);
+  const [debugFrames, setDebugFrames] = useState(false);
 
   // const [calibrationFinishedSound] = useSound(doneSound);
 
@@ -74,6 +79,10 @@ export default function MainPage() {
   // const [calibratePoint, setCalibratePoint] = useState<number[]>([540.0, 440.0]);
   // const [calibratePoint, setCalibratePoint] = useState<number[]>([609.0, 385.0]);
   const [calibratePoint, setCalibratePoint] = useState<number[]>([0.0, 0.0]);
+  // mm per pixel measured during calibration, the backend default is used until then
+  const [calibrateScale, setCalibrateScale] = useState<number | null>(null);
+  // target calibrated on, its aiming black gives the scale
+  const [target, setTarget] = useState<"air_pistol" | "air_rifle">("air_pistol");
   const [fineAdjustment, setFineAdjustment] = useState<number[]>([0.0, 0.0]);
   const [fineAdjustmentEnd, setFineAdjustmentEnd] = useState<number[]>([0.0, 0.0]);
   const [fineAdjustmentStarted, setFineAdjustmentStarted] = useState(false);
@@ -141,7 +150,8 @@ export default function MainPage() {
     invoke('start_calib_video', {
       cameraLabel: cameraId,
       minThresh: cameraThreshs[0],
-      maxThresh: cameraThreshs[1]
+      maxThresh: cameraThreshs[1],
+      target: target
     }).then(() => {
       invoke('start_audio', {
         micLabel: micId,
@@ -150,11 +160,13 @@ export default function MainPage() {
     });
 
     listen('calibration_finished', (event) => {
-      let result = event.payload as { success: boolean, calibrate_point: number[], error_msg: string };
+      let result = event.payload as { success: boolean, calibrate_point: number[], scale: number | null, error_msg: string };
       // calibrationFinishedSound();
       if (result.success) {
         setCalibrationError("");
         setCalibratePoint(result.calibrate_point);
+        // a calibration without a measured scale falls back to the backend default
+        setCalibrateScale(result.scale);
       } else {
         setCalibrationError(result.error_msg);
       }
@@ -176,9 +188,14 @@ export default function MainPage() {
     invoke('start_shoot_video', {
       cameraLabel: cameraId,
       calibratePoint: calibratePoint,
+      scale: calibrateScale,
       fineAdjust: fineAdjustment,
       minThresh: cameraThreshs[0],
-      maxThresh: cameraThreshs[1]
+      maxThresh: cameraThreshs[1],
+      recordPath: recordPath,
+      smoothing: smoothing,
+      adaptiveThresholds: adaptiveThresholds,
+      debugFrames: debugFrames
     }).then(() => {
       invoke('start_audio', {
         micLabel: micId,
@@ -417,6 +434,16 @@ export default function MainPage() {
     }).then(unlisten => {
       unlistens.push(unlisten);
     });
+    listen('camera_lost', (_) => {
+      showToast("error", "Lost connection to camera, trying to reconnect...");
+    }).then(unlisten => {
+      unlistens.push(unlisten);
+    });
+    listen('camera_restored', (_) => {
+      showToast("success", "Camera reconnected");
+    }).then(unlisten => {
+      unlistens.push(unlisten);
+    });
 
     return () => {
       // stop running threads
@@ -428,7 +455,7 @@ export default function MainPage() {
     }
   }, []);
 
-  async function chooseDefaultCameraAndMic() {
+  async function listWebviewDevices() {
     // initiate permission
     const stream = await navigator.mediaDevices.getUserMedia({
         video: true,
@@ -445,7 +472,8 @@ export default function MainPage() {
     // get webcams
     const webcams = mydevices
       .filter((device) => device.kind === "videoinput")
-      .map((device, _1, _2) => device.label.split(' (')[0]);
+      .map((device, _1, _2) => device.label.split(' (')[0])
+      .map((label) => ({ id: label, name: label }));
 
     // get mics
     const mics = mydevices.filter(
@@ -453,6 +481,21 @@ export default function MainPage() {
         device.kind === "audioinput" && !device.label.startsWith("Default") && !device.label.startsWith("Communications")
     ).map((device, _1, _2) => device.label.split(/ \([0-9]/)[0]);
 
+    return { webcams, mics };
+  }
+
+  async function chooseDefaultCameraAndMic() {
+    // devices are enumerated by the backend so that ids match the native device names
+    let webcams = await invoke<WebcamInfo[]>('list_cameras', { probe: false });
+    let mics = (await invoke<DeviceInfo[]>('list_mics')).map((device) => device.id);
+
+    if (webcams.length == 0 || mics.length == 0) {
+      // backend enumeration is not supported on every OS, fall back to the webview labels
+      const webviewDevices = await listWebviewDevices();
+      webcams = webcams.length == 0 ? webviewDevices.webcams : webcams;
+      mics = mics.length == 0 ? webviewDevices.mics : mics;
+    }
+
     const user_chose_video = cameraId != "";
     const user_chose_audio = micId != "";
     let usbAudioExists = false;
@@ -460,8 +503,8 @@ export default function MainPage() {
 
     // choose webcam with name "USB" if user has not selected video
     for (let i = 0; i < webcams.length; i++) {
-      if (webcams[i].includes("USB") && !user_chose_video) {
-        setCameraId(webcams[i]);
+      if (webcams[i].name.toUpperCase().includes("USB") && !user_chose_video) {
+        setCameraId(webcams[i].id);
         usbVideoExists = true;
       }
     }
@@ -488,7 +531,7 @@ export default function MainPage() {
       (!user_chose_video && !usbVideoExists) ||
       (!user_chose_audio && !usbAudioExists)
     ) {
-      setCameraId(webcams[0]);
+      setCameraId(webcams[0].id);
       setMicId(mics[0]);
       showToast(
         "info",
@@ -524,6 +567,14 @@ export default function MainPage() {
           <Button color="secondary" onClick={testClick}>
             TEST
           </Button>
+          <Button
+            color={"inherit"}
+            onClick={() => setTarget(target == "air_pistol" ? "air_rifle" : "air_pistol")}
+            disabled={calibrateStarted}
+            style={{ marginRight: "10px" }}
+          >
+            {target == "air_pistol" ? "AIR PISTOL" : "AIR RIFLE"}
+          </Button>
           <Button
             color={"info"}
             onClick={calibrateClick}
@@ -575,6 +626,14 @@ export default function MainPage() {
                 mics={mics}
                 cameraId={cameraId}
                 micId={micId}
+                recordPath={recordPath}
+                setRecordPath={setRecordPath}
+                smoothing={smoothing}
+                setSmoothing={setSmoothing}
+                adaptiveThresholds={adaptiveThresholds}
+                setAdaptiveThresholds={setAdaptiveThresholds}
+                debugFrames={debugFrames}
+                setDebugFrames={setDebugFrames}
                 handleClose={handleSettingsPageClose}
               />
             </Box>
@@ -733,4 +792,8 @@ export default function MainPage() {
       </Snackbar>
     </div>
   );
-}
\ No newline at end of file
+}
+interface DeviceInfo {
+  id: string;
+  name: string;
+}
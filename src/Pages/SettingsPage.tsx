@@ -1,6 +1,7 @@
 import { Typography, Box, Button } from "@mui/material";
-import Webcam from "./components/Webcam";
+import Webcam, { WebcamInfo } from "./components/Webcam";
 import Mic from "./components/Mic";
+import ShootOptions from "./components/ShootOptions";
 
 const SettingsPage = ({
   setCameraId,
@@ -13,6 +14,14 @@ const SettingsPage = ({
   mics,
   cameraId,
   micId,
+  recordPath,
+  setRecordPath,
+  smoothing,
+  setSmoothing,
+  adaptiveThresholds,
+  setAdaptiveThresholds,
+  debugFrames,
+  setDebugFrames,
   handleClose
 }: IProps) => {
   return (
@@ -35,6 +44,9 @@ const SettingsPage = ({
           <Webcam setCameraId={setCameraId} setCameraThreshs={setCameraThreshs} cameraThreshs={cameraThreshs} webcams={webcams} cameraId={cameraId} /> 
         </Box>
       </Box>
+      <Box sx={{ p: 1, m: 1 }}>
+        <ShootOptions recordPath={recordPath} setRecordPath={setRecordPath} smoothing={smoothing} setSmoothing={setSmoothing} adaptiveThresholds={adaptiveThresholds} setAdaptiveThresholds={setAdaptiveThresholds} debugFrames={debugFrames} setDebugFrames={setDebugFrames} />
+      </Box>
       <Box textAlign='center'>
         <Button variant='contained' color='secondary' onClick={handleClose}>
           Save & Close
@@ -51,10 +63,18 @@ interface IProps {
   setMicId: (id: string) => void;
   setMicThresh: (thresh: number) => void;
   micThresh: number;
-  webcams: string[];
+  webcams: WebcamInfo[];
   mics: string[];
   cameraId: string;
   micId: string;
+  recordPath: string | null;
+  setRecordPath: (path: string | null) => void;
+  smoothing: number;
+  setSmoothing: (smoothing: number) => void;
+  adaptiveThresholds: boolean;
+  setAdaptiveThresholds: (adaptiveThresholds: boolean) => void;
+  debugFrames: boolean;
+  setDebugFrames: (debugFrames: boolean) => void;
   handleClose: () => void;
 }
 
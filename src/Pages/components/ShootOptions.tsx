@@ -0,0 +1,111 @@
+import { Button, Slider, Stack, Switch, Typography } from "@mui/material";
+import { save } from '@tauri-apps/api/dialog';
+import { once } from '@tauri-apps/api/event';
+import { invoke } from '@tauri-apps/api/tauri';
+import { useState } from "react";
+
+const ShootOptions = ({ recordPath, setRecordPath, smoothing, setSmoothing, adaptiveThresholds, setAdaptiveThresholds, debugFrames, setDebugFrames }: IProps) => {
+  const chooseRecordPath = async () => {
+    const selected = await save({
+      filters: [{
+        name: 'Video',
+        extensions: ['mkv', 'mp4']
+      }]
+    });
+    if (selected !== null) {
+      setRecordPath(selected);
+    }
+  };
+
+  // the saved frames are packed into a zip to attach to a bug report
+  const [exportMsg, setExportMsg] = useState("");
+  const exportDebugFrames = async () => {
+    const selected = await save({
+      filters: [{
+        name: 'Zip',
+        extensions: ['zip']
+      }]
+    });
+    if (selected === null) {
+      return;
+    }
+
+    await once('debug_frames_zipped', (event) => {
+      const result = event.payload as { success: boolean, error_msg: string };
+      setExportMsg(result.success ? "Exported" : result.error_msg);
+    });
+    invoke('zip_debug_frames', { path: selected });
+  };
+
+  return (
+    <div>
+      <Typography textAlign="center" variant="h5">
+        Shooting
+      </Typography>
+      <Stack spacing={2} direction="row" sx={{ mb: 1 }} alignItems="center">
+        <Typography textAlign="center" variant="body1">
+          Record
+        </Typography>
+        <Button onClick={chooseRecordPath} variant="outlined">
+          {recordPath === null ? "Choose a file" : recordPath.split(/(\\|\/)/g).pop()}
+        </Button>
+        {recordPath !== null ? (
+          <Button onClick={() => setRecordPath(null)} variant="contained" color="error">
+            Don't record
+          </Button>
+        ) : null}
+      </Stack>
+      <Stack spacing={2} direction="row" sx={{ mb: 1 }} alignItems="center">
+        <Typography textAlign="center" variant="body1">
+          Smoothing
+        </Typography>
+        <Slider
+          value={smoothing}
+          step={0.05}
+          min={0}
+          max={1}
+          valueLabelDisplay="auto"
+          onChange={(_1, newSmoothing, _2) => {
+            // @ts-expect-error: expect error here due to possibility that newSmoothing be an array
+            setSmoothing(newSmoothing);
+          }}
+        />
+      </Stack>
+      <Stack spacing={2} direction="row" sx={{ mb: 1 }} alignItems="center">
+        <Typography textAlign="center" variant="body1">
+          Follow lighting
+        </Typography>
+        <Switch checked={adaptiveThresholds} onChange={(e) => setAdaptiveThresholds(e.target.checked)} />
+      </Stack>
+      <Stack spacing={2} direction="row" sx={{ mb: 1 }} alignItems="center">
+        <Typography textAlign="center" variant="body1">
+          Save failed detections
+        </Typography>
+        <Switch checked={debugFrames} onChange={(e) => setDebugFrames(e.target.checked)} />
+        <Button onClick={exportDebugFrames} variant="outlined">
+          Export
+        </Button>
+        <Typography variant="body2">
+          {exportMsg}
+        </Typography>
+      </Stack>
+    </div>
+  );
+};
+
+interface IProps {
+  // the camera stream of each shooting session is saved here, with its triggers and shots
+  recordPath: string | null;
+  setRecordPath: (path: string | null) => void;
+  // how much jitter is filtered out of the trace, 0 shows the trace as detected
+  smoothing: number;
+  setSmoothing: (smoothing: number) => void;
+  // move the detection thresholds with lighting changes during the session
+  adaptiveThresholds: boolean;
+  setAdaptiveThresholds: (adaptiveThresholds: boolean) => void;
+  // write the frames the marker was not found in to the debug folder
+  debugFrames: boolean;
+  setDebugFrames: (debugFrames: boolean) => void;
+}
+
+export default ShootOptions;
@@ -47,6 +47,14 @@ const Webcam = ({ setCameraId, setCameraThreshs, cameraThreshs, webcams, cameraI
     });
   }
 
+  // cameras are opened by id but shown by name, files by their file name
+  const displayName = (id: string, is_file: boolean) => {
+    if (is_file) {
+      return id.split(/(\\|\/)/g).pop() as string;
+    }
+    return webcams.find((webcam) => webcam.id === id)?.name ?? id;
+  };
+
   useEffect(() => {
     grabFrames();
     selectWebcam(cameraId, cameraId.includes("/") || cameraId.includes("\\"));
@@ -78,11 +86,7 @@ const Webcam = ({ setCameraId, setCameraThreshs, cameraThreshs, webcams, cameraI
 
     // update state
     setCameraId(device_label);
-    if (is_file) {
-      setDeviceLabel(device_label.split(/(\\|\/)/g).pop() as string);
-    } else {
-      setDeviceLabel(device_label);
-    }
+    setDeviceLabel(displayName(device_label, is_file));
     setWebcamStarted(true);
     closeWebcams();
 
@@ -125,6 +129,17 @@ const Webcam = ({ setCameraId, setCameraThreshs, cameraThreshs, webcams, cameraI
     setCameraThreshs(newCameraThreshs);
   };
 
+  // the preview samples frames for a moment, the tuned thresholds are already applied to it
+  const [autoTuning, setAutoTuning] = useState(false);
+  const autoTune = async () => {
+    setAutoTuning(true);
+    const tuning = await invoke<{ min_thresh: number, max_thresh: number, score: number } | null>('auto_tune_thresholds');
+    setAutoTuning(false);
+    if (tuning !== null) {
+      setCameraThreshs([tuning.min_thresh, tuning.max_thresh]);
+    }
+  };
+
   const chooseFile = async () => {
     const selected = await open({
       multiple: false,
@@ -156,8 +171,8 @@ const Webcam = ({ setCameraId, setCameraThreshs, cameraThreshs, webcams, cameraI
         }}
       >
         {webcams.map((webcam) => (
-          <MenuItem key={webcam} onClick={() => selectWebcam(webcam, false)}>
-            {webcam}
+          <MenuItem key={webcam.id} onClick={() => selectWebcam(webcam.id, false)}>
+            {webcam.name}
           </MenuItem>
         ))}
         <MenuItem key="Choose File" onClick={chooseFile}>
@@ -221,16 +236,25 @@ const Webcam = ({ setCameraId, setCameraThreshs, cameraThreshs, webcams, cameraI
           // @ts-expect-error: expect error here due to possibility that newLevel be an array
           onChange={(_1, newThreshs, _2) => cameraThreshsChanged(newThreshs)}
         />
+        <Button onClick={autoTune} variant="outlined" disabled={!webcamStarted || autoTuning}>
+          {autoTuning ? "Tuning" : "Auto"}
+        </Button>
       </Stack>
     </div>
   );
 };
 
+// a camera as listed by the backend, `id` opens it and `name` is shown
+export interface WebcamInfo {
+  id: string;
+  name: string;
+}
+
 interface IProps {
   setCameraId: (id: string) => void;
   setCameraThreshs: (threshs: number[]) => void;
   cameraThreshs: number[];
-  webcams: string[];
+  webcams: WebcamInfo[];
   cameraId: string;
 }
 
@@ -141,4 +141,10 @@ export interface TracePoint {
   x: number;
   y: number;
   time: number;
+  // 0 to 1, how sure the detector was of the marker position
+  confidence?: number;
+  // predicted while the marker was not detected
+  interpolated?: boolean;
+  // position before smoothing, only set when the trace is smoothed
+  raw?: [number, number];
 }